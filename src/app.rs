@@ -1,12 +1,27 @@
-use std::{collections::HashSet, error, vec};
-use base64::prelude::*;
+use std::{error, vec};
+use std::time::{Duration, Instant};
 
-use futures::prelude::*;
+use crate::buffer::{Buffer, BufferKey, BufferKind, SEARCH_BUFFER, SERVER_BUFFER};
+use crate::cap::Capabilities;
+use crate::connection::{Backoff, ConnectionState, Lag};
+use crate::format;
+use crate::history::{self, HistoryBatch, DEFAULT_HISTORY_LINES};
+use crate::colors::{self, NickColors};
+use crate::command::{self, Input};
+use crate::isupport::ServerInfo;
+use crate::logger::{self, Logger, DEFAULT_BACKLOG_LINES};
+use crate::message::{command_params, ChatLine, Delivery, LineKind};
+use crate::nick::{self, Regain};
+use crate::operator::{self, BanMaskStyle};
+use crate::sasl::{Mechanism, Sasl};
+use crate::search::{BufferSearch, Query, Scope, MAX_RESULTS};
+use crate::ui::{self, DEFAULT_TIMESTAMP_FORMAT};
 
 use std::collections::HashMap as Hashmap;
-use ratatui::widgets::ScrollbarState;
-use tokio::time::timeout;
-use std::time::Duration;
+use chrono::format::{Item, StrftimeItems};
+use irc::client::data::Config;
+use irc::client::Client;
+use irc::proto::{BatchSubCommand, CapSubCommand, Command, Message, Prefix, Response, Tag};
 
 /// Application result type.
 pub type AppResult<T> = std::result::Result<T, Box<dyn error::Error>>;
@@ -15,7 +30,9 @@ pub type AppResult<T> = std::result::Result<T, Box<dyn error::Error>>;
 #[derive(Debug)]
 pub enum Mode {
     Normal,
-    Command
+    Command,
+    /// Incremental search in the active buffer.
+    Search,
 }
 
 #[derive(Debug)]
@@ -25,54 +42,82 @@ pub struct App{
     pub mode: Mode,
     pub show_users: bool,
     pub selected_tab: usize,
-    pub num_tabs: usize,
-    pub tab_titles: Vec<String>,
-    pub messaged: HashSet<String>,
-
-
-    pub messages: Hashmap<String, Vec<String>>,
+    /// Open buffers in tab order, the server buffer first.
+    pub buffers: Vec<Buffer>,
     pub input: String,
+    /// Incremental search of [`Mode::Search`].
+    pub buffer_search: BufferSearch,
 
-    pub client: Option<irc::client::Client>,
-    pub stream: Option<irc::client::ClientStream>,
+    pub client: Option<Client>,
+    pub connection: ConnectionState,
+    pub backoff: Backoff,
+    pub lag: Lag,
+    /// Channels to join again once reconnected.
+    pub rejoin: Vec<String>,
     pub config: Option<irc::client::data::Config>,
+    /// Current nickname, following NICK changes.
     pub username: String,
-    pub active_channel: String,
-    pub active_channel_users: Vec<String>,    
+    /// Nicknames tried when the primary one is taken, in order.
+    pub nick_candidates: Vec<String>,
+    /// Candidates tried so far on this connection.
+    pub nick_attempt: usize,
+    /// Whether services logged us in on this connection (900 or SASL success).
+    pub identified: bool,
+    /// Last seen user and host of each nick, used for ban masks.
+    pub hosts: Hashmap<String, (String, String)>,
+    pub server_info: ServerInfo,
+    /// SASL exchange, when credentials are configured.
+    pub sasl: Option<Sasl>,
+    pub caps: Capabilities,
+    /// Whether capability negotiation is holding registration.
+    pub negotiating: bool,
+    /// Last label attached to an outgoing message.
+    pub last_label: u64,
+    /// Open `chathistory` batches by reference tag.
+    pub history_batches: Hashmap<String, HistoryBatch>,
+    /// Labels of open `labeled-response` batches by reference tag.
+    pub label_batches: Hashmap<String, String>,
+    /// Writes buffer lines to disk, unless logging is turned off.
+    pub logger: Option<Logger>,
+    pub nick_colors: NickColors,
 
     pub cursor_position: usize,
-    pub vertical_scroll: Vec<u16>,
-    pub vertical_scroll_state: Vec<ScrollbarState>,
-    pub horizontal_scroll: Vec<u16>,
-    pub horizontal_scroll_state: Vec<ScrollbarState>,
 }
 
 impl Default for App {
     fn default() -> Self {
+        let server_info = ServerInfo::default();
         Self {
             running: true,
             mode: Mode::Normal,
             show_users: true,
             selected_tab: 0,
-            num_tabs: 1,
-            tab_titles: vec![],
-            messaged: HashSet::new(),
-
-            messages: Hashmap::new(),
+            buffers: vec![Buffer::new(BufferKind::Server, SERVER_BUFFER, server_info.casemapping)],
             input: String::new(),
+            buffer_search: BufferSearch::default(),
 
             client: None,
-            stream: None,
+            connection: ConnectionState::default(),
+            backoff: Backoff::default(),
+            lag: Lag::default(),
+            rejoin: vec![],
             config: None,
             username: "".to_string(),
-            active_channel: "".to_string(),
-            active_channel_users: Vec::new(),
+            nick_candidates: vec![],
+            nick_attempt: 0,
+            identified: false,
+            hosts: Hashmap::new(),
+            server_info,
+            sasl: None,
+            caps: Capabilities::default(),
+            negotiating: false,
+            last_label: 0,
+            history_batches: Hashmap::new(),
+            label_batches: Hashmap::new(),
+            logger: None,
+            nick_colors: NickColors::default(),
 
             cursor_position: 0,
-            vertical_scroll: vec![],
-            horizontal_scroll: vec![],
-            vertical_scroll_state: vec![],
-            horizontal_scroll_state: vec![],
         }
     }
 }
@@ -83,41 +128,1247 @@ impl App {
         Self::default()
     }
 
-    pub async fn tick(&mut self) {
-        if let Ok(Some(Ok(message))) = timeout(Duration::from_millis(5), self.stream.as_mut().unwrap().next()).await {
-            if let Some(channel) = message.response_target() {
-                if channel.contains('#') {
-                    let message_content = message.to_string();
-                    self.messages.entry(channel.to_string()).or_default().push(message_content);
-                    let tab_index = self.tab_titles.iter().position(|x| x == channel).unwrap();
+    /// Handles a message received from the server.
+    pub fn handle_message(&mut self, message: Message) {
+        self.lag.activity(Instant::now());
+        if let Command::PONG(server, token) = &message.command {
+            // Answers to our own lag checks are not shown.
+            if self.lag.pong(token.as_ref().unwrap_or(server), Instant::now()) {
+                return;
+            }
+        }
+        if let Some(Prefix::Nickname(nick, user, host)) = &message.prefix {
+            if !user.is_empty() && !host.is_empty() {
+                self.hosts.insert(self.server_info.normalize(nick), (user.clone(), host.clone()));
+            }
+        }
+        if let Command::Response(Response::RPL_ISUPPORT, args) = &message.command {
+            let casemapping = self.server_info.casemapping;
+            self.server_info.update(args);
+            if casemapping != self.server_info.casemapping {
+                self.refold_keys();
+            }
+        }
+        match &message.command {
+            Command::Response(Response::RPL_WELCOME, args) => {
+                if let Some(nick) = args.first() {
+                    self.username = nick.clone();
+                }
+                self.registered();
+                // SASL identifies us before registration completes.
+                if self.identified {
+                    self.regain_nick();
+                }
+            },
+            Command::Response(Response::RPL_LOGGEDIN | Response::RPL_SASLSUCCESS, _) if !self.identified => {
+                self.identified = true;
+                if self.connection == ConnectionState::Connected {
+                    self.regain_nick();
+                }
+            },
+            Command::Response(Response::RPL_ENDOFMOTD | Response::ERR_NOMOTD, _) => {
+                self.rejoin_channels();
+                self.monitor_nick();
+            },
+            _ => {},
+        }
+        self.watch_nick(&message);
+        self.negotiate(&message);
+        // Negotiation is reported through status lines, SASL failures also as numerics.
+        if let Command::CAP(..) | Command::AUTHENTICATE(_) = &message.command {
+            return;
+        }
+        let line = ChatLine::from_message(&message);
+        if self.collect_history(&message, &line) {
+            return;
+        }
+        if self.reconcile(&message, &line) {
+            return;
+        }
+        let buffers = self.route(&message, &line);
+        self.track_membership(&message);
+        let membership = matches!(message.command, Command::JOIN(..) | Command::PART(..) | Command::KICK(..));
+        for buffer in buffers {
+            // Like `echo`, a PART or KICK for a channel we already closed does not reopen it.
+            if membership && self.buffer(&buffer).is_none() {
+                continue;
+            }
+            self.push_line(&buffer, line.clone());
+        }
+    }
+
+    /// Opens capability negotiation and registers the connection.
+    ///
+    /// Registration is held until SASL, when configured, has finished.
+    pub fn register(&mut self) {
+        let config = self.config.clone().unwrap();
+        self.sasl = self.sasl_from_config(&config);
+        self.caps = Capabilities::from_option(config.get_option("capabilities"));
+        self.negotiating = true;
+        self.send(Command::CAP(None, CapSubCommand::LS, Some("302".to_string()), None));
+        if !config.password().is_empty() {
+            self.send(Command::PASS(config.password().to_string()));
+        }
+        let nickname = config.nickname().map(|nickname| nickname.to_string()).unwrap_or_else(|_| self.username.clone());
+        self.username = nickname.clone();
+        self.send(Command::NICK(nickname));
+        self.send(Command::USER(config.username().to_string(), "0".to_string(), config.realname().to_string()));
+    }
+
+    /// Config for the next connection.
+    ///
+    /// The irc crate would answer every 433 and 432 with its `alt_nicks`,
+    /// user `/nick` changes included, so it gets none and reports those as
+    /// [`Event::NickUnavailable`](crate::event::Event::NickUnavailable) instead.
+    pub fn connect_config(&mut self) -> Config {
+        let mut config = self.config.clone().unwrap();
+        self.nick_candidates = nick::candidates(&self.primary_nick(), config.alternate_nicknames(), self.server_info.nicklen);
+        self.nick_attempt = 0;
+        config.alt_nicks = vec![];
+        config
+    }
+
+    /// Configured nickname.
+    fn primary_nick(&self) -> String {
+        self.config.as_ref()
+            .and_then(|config| config.nickname().ok())
+            .unwrap_or(&self.username)
+            .to_string()
+    }
+
+    /// Follows nickname fallbacks during registration and takes the primary
+    /// nickname back when its holder leaves.
+    fn watch_nick(&mut self, message: &Message) {
+        let primary = self.primary_nick();
+        match &message.command {
+            Command::Response(Response::ERR_NICKNAMEINUSE | Response::ERR_ERRONEOUSNICKNAME | Response::ERR_NICKCOLLISION, _)
+                if self.connection == ConnectionState::Registering => self.next_nick(),
+            Command::Response(Response::RPL_MONOFFLINE, args) if !self.is_me(&primary) => {
+                let offline = args.last().is_some_and(|targets| {
+                    targets.split(',').any(|target| self.server_info.eq(target.split('!').next().unwrap_or(target), &primary))
+                });
+                if offline {
+                    self.send(Command::NICK(primary));
+                }
+            },
+            // Without MONITOR, catch the holder leaving a shared channel.
+            Command::QUIT(_) | Command::NICK(_) if self.server_info.monitor.is_none()
+                && !self.is_me(&primary)
+                && message.source_nickname().is_some_and(|nick| self.server_info.eq(nick, &primary)) => {
+                self.send(Command::NICK(primary));
+            },
+            _ => {},
+        }
+    }
+
+    /// Handles a nickname refused with 433 or 432, which the irc crate
+    /// reports in place of the message. Only registration falls back to the
+    /// next candidate, a refused `/nick` is just reported.
+    pub fn nick_unavailable(&mut self) {
+        match self.connection {
+            ConnectionState::Registering => self.next_nick(),
+            _ => self.status("Error: That nickname is taken or not allowed"),
+        }
+    }
+
+    /// Registers with the next fallback nickname, if any is left.
+    fn next_nick(&mut self) {
+        let taken = self.username.clone();
+        match self.nick_candidates.get(self.nick_attempt).cloned() {
+            Some(next) => {
+                self.server_status(format!("Nickname {} is unavailable, trying {}", taken, next));
+                self.nick_attempt += 1;
+                self.username = next.clone();
+                self.send(Command::NICK(next));
+            },
+            None => self.server_status(format!("Nickname {} is unavailable and no alternates are left", taken)),
+        }
+    }
+
+    /// Asks NickServ for the primary nickname once identified while
+    /// registered under another one, as set by the `nick_regain` option.
+    /// Only `nick_password` is sent along, never the server password.
+    fn regain_nick(&mut self) {
+        let primary = self.primary_nick();
+        if self.is_me(&primary) {
+            return;
+        }
+        let Some(config) = &self.config else {
+            return;
+        };
+        let Some(regain) = config.get_option("nick_regain").and_then(|regain| regain.parse::<Regain>().ok()) else {
+            return;
+        };
+        let commands = regain.commands(&primary, config.nick_password());
+        self.server_status(format!("Recovering nickname {} through NickServ", primary));
+        for command in commands {
+            self.send(command);
+        }
+    }
+
+    /// Watches the primary nickname when registered under another one.
+    fn monitor_nick(&mut self) {
+        let primary = self.primary_nick();
+        if self.is_me(&primary) {
+            return;
+        }
+        if self.server_info.monitor.is_some() {
+            self.send(Command::Raw("MONITOR".to_string(), vec!["+".to_string(), primary]));
+        }
+    }
+
+    /// Takes over a freshly connected client and starts registration.
+    pub fn connected(&mut self, client: Client) {
+        self.client = Some(client);
+        self.connection = ConnectionState::Registering;
+        self.lag = self.lag_from_config();
+        self.lag.reset(Instant::now());
+        self.server_status("Connected, registering");
+        self.register();
+    }
+
+    /// Handles a lost connection or a failed attempt and schedules the next attempt.
+    pub fn disconnected(&mut self, reason: &str) {
+        // The connection task of a timed out connection may still report its end.
+        if !self.running || matches!(self.connection, ConnectionState::Disconnected { .. }) {
+            return;
+        }
+        let was_connected = self.client.take().is_some();
+        self.negotiating = false;
+        self.caps.reset();
+        self.history_batches.clear();
+        self.label_batches.clear();
+        self.identified = false;
+        let joined: Vec<String> = self.buffers.iter()
+            .filter(|buffer| buffer.joined)
+            .map(|buffer| buffer.name().to_string())
+            .collect();
+        // A failed attempt leaves nothing joined, keep the list from the lost connection.
+        if !joined.is_empty() {
+            self.rejoin = joined;
+        }
+        for buffer in self.buffers.iter_mut() {
+            buffer.joined = false;
+            buffer.members.clear();
+            buffer.history_pending = false;
+            for line in buffer.lines.iter_mut().filter(|line| line.delivery == Delivery::Pending) {
+                line.delivery = Delivery::Failed;
+            }
+        }
+        if self.backoff.attempt == 0 {
+            self.backoff = self.backoff_from_config();
+        }
+        let delay = self.backoff.next_delay();
+        self.connection = ConnectionState::Disconnected { retry_at: Instant::now() + delay };
+        let text = format!("Disconnected: {}, reconnecting in {}s", reason, delay.as_secs());
+        if was_connected {
+            self.broadcast_status(text);
+        } else {
+            self.server_status(text);
+        }
+    }
+
+    /// Reconnection delays from the `reconnect_delay` and `reconnect_max_delay` options, in seconds.
+    fn backoff_from_config(&self) -> Backoff {
+        let defaults = Backoff::default();
+        Backoff::new(self.seconds_option("reconnect_delay", defaults.base), self.seconds_option("reconnect_max_delay", defaults.max))
+    }
+
+    /// Lag checks from the `ping_interval` and `ping_timeout` options, in seconds.
+    fn lag_from_config(&self) -> Lag {
+        let defaults = Lag::default();
+        Lag::new(self.seconds_option("ping_interval", defaults.interval), self.seconds_option("ping_timeout", defaults.timeout))
+    }
+
+    /// A duration option given in seconds, or `default` when unset or invalid.
+    fn seconds_option(&self, option: &str, default: Duration) -> Duration {
+        self.config.as_ref()
+            .and_then(|config| config.get_option(option))
+            .and_then(|seconds| seconds.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(default)
+    }
+
+    /// Whether a reconnection attempt is due. Moves to the connecting state
+    /// when it is, leaving the caller to open the connection.
+    pub fn reconnect_due(&mut self) -> bool {
+        match self.connection {
+            ConnectionState::Disconnected { retry_at } if Instant::now() >= retry_at => {
+                self.connection = ConnectionState::Connecting;
+                self.server_status(format!("Reconnecting (attempt {})", self.backoff.attempt));
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Registration completed: run the on-join options and refill query buffers.
+    fn registered(&mut self) {
+        self.connection = ConnectionState::Connected;
+        if self.backoff.attempt > 0 {
+            self.broadcast_status("Reconnected");
+        }
+        self.backoff.reset();
+        self.process_config_options();
+        let queries: Vec<String> = self.buffers.iter()
+            .filter(|buffer| buffer.kind == BufferKind::Query)
+            .map(|buffer| buffer.name().to_string())
+            .collect();
+        for query in queries {
+            self.fetch_history(&query, false);
+        }
+    }
+
+    /// Joins the channels that were open before the connection was lost.
+    /// Channels from the config are left to the irc crate's own auto-join.
+    fn rejoin_channels(&mut self) {
+        let autojoin: Vec<String> = self.config.as_ref()
+            .map(|config| config.channels().to_vec())
+            .unwrap_or_default();
+        for channel in std::mem::take(&mut self.rejoin) {
+            if autojoin.iter().any(|autojoin| self.server_info.eq(autojoin, &channel)) {
+                continue;
+            }
+            let key = self.buffer(&channel).and_then(|buffer| buffer.channel_key.clone());
+            self.send(Command::JOIN(channel, key, None));
+        }
+    }
+
+    /// Sends a message to the server, reporting in the active buffer when
+    /// that fails. Returns whether it was sent.
+    pub fn send(&mut self, message: impl Into<Message>) -> bool {
+        let result = match &self.client {
+            Some(client) => client.send(message).map_err(|err| err.to_string()),
+            None => Err("Not connected".to_string()),
+        };
+        match result {
+            Ok(()) => true,
+            Err(err) => {
+                self.status(format!("Error: {}", err));
+                false
+            }
+        }
+    }
+
+    /// Builds the SASL exchange from the `sasl_*` options, if there is
+    /// something to authenticate with.
+    fn sasl_from_config(&mut self, config: &Config) -> Option<Sasl> {
+        let configured = config.get_option("sasl_mechanism");
+        let mechanism = match configured.map(str::parse::<Mechanism>) {
+            None => Mechanism::Plain,
+            Some(Ok(mechanism)) => mechanism,
+            Some(Err(err)) => {
+                self.server_status(format!("Error: {}", err));
+                return None;
+            }
+        };
+        let has_credentials = match mechanism {
+            Mechanism::External => config.client_cert_path().is_some(),
+            _ => !config.password().is_empty(),
+        };
+        if !has_credentials {
+            if configured.is_some() {
+                self.server_status(format!("Skipping SASL {}: no credentials configured", mechanism.name()));
+            }
+            return None;
+        }
+        let username = config.get_option("sasl_username").or(config.nickname().ok()).unwrap_or_default();
+        Some(Sasl::new(mechanism, username, config.password()))
+    }
+
+    /// Drives capability negotiation and SASL from the server's replies.
+    fn negotiate(&mut self, message: &Message) {
+        match &message.command {
+            Command::CAP(_, CapSubCommand::LS, first, second) => {
+                // Every line of a multi-line reply but the last is marked with `*`.
+                let (more, caps) = match (first, second) {
+                    (Some(star), Some(caps)) if star == "*" => (true, caps),
+                    (Some(caps), None) => (false, caps),
+                    _ => return,
+                };
+                if !self.caps.ls(caps, more) || !self.negotiating {
+                    return;
+                }
+                let mut request = self.caps.to_request();
+                if let Some(sasl) = &self.sasl {
+                    let mechanism = sasl.mechanism.name();
+                    match self.caps.value("sasl") {
+                        None => self.server_status("Skipping SASL: the server does not offer it"),
+                        // CAP 302 lists the mechanisms, older servers leave it to 908.
+                        Some(value) if value.is_empty() || value.split(',').any(|offered| offered.eq_ignore_ascii_case(mechanism)) => {
+                            request.push("sasl".to_string());
+                        },
+                        Some(value) => self.server_status(format!("Skipping SASL: {} is not offered, only {}", mechanism, value)),
+                    }
+                }
+                if request.is_empty() {
+                    self.end_negotiation();
+                } else {
+                    self.request_caps(request);
+                }
+            },
+            Command::CAP(_, CapSubCommand::ACK, Some(caps), _) => {
+                self.caps.ack(caps);
+                if !self.negotiating {
+                    self.server_status(format!("Capabilities changed: {}", caps));
+                    return;
+                }
+                let start = match (&mut self.sasl, caps.split_whitespace().any(|cap| cap == "sasl")) {
+                    (Some(sasl), true) => Some(sasl.start()),
+                    _ => None,
+                };
+                match start {
+                    Some(command) => {
+                        self.send(command);
+                    },
+                    None if self.caps.pending.is_empty() && !self.caps.is_enabled("sasl") => self.end_negotiation(),
+                    None => {},
+                }
+            },
+            Command::CAP(_, CapSubCommand::NAK, first, second) => {
+                let caps = second.as_ref().or(first.as_ref()).cloned().unwrap_or_default();
+                self.caps.nak(&caps);
+                self.server_status(format!("Capabilities refused: {}", caps));
+                if self.negotiating && self.caps.pending.is_empty() {
+                    self.end_negotiation();
+                }
+            },
+            Command::CAP(_, CapSubCommand::NEW, Some(caps), _) => {
+                let request = self.caps.new_caps(caps);
+                self.server_status(format!("Capabilities offered: {}", caps));
+                if !request.is_empty() {
+                    self.request_caps(request);
+                }
+            },
+            Command::CAP(_, CapSubCommand::DEL, Some(caps), _) => {
+                let removed = self.caps.del(caps);
+                if !removed.is_empty() {
+                    self.server_status(format!("Capabilities withdrawn: {}", removed.join(" ")));
+                }
+            },
+            Command::AUTHENTICATE(data) => {
+                let Some(sasl) = self.sasl.as_mut() else {
+                    return;
+                };
+                match sasl.challenge(data) {
+                    Ok(lines) => {
+                        for line in lines {
+                            self.send(line);
+                        }
+                    },
+                    Err(err) => {
+                        self.server_status(format!("Aborting SASL: {}", err));
+                        self.send(Command::AUTHENTICATE("*".to_string()));
+                    },
+                }
+            },
+            Command::Response(Response::RPL_SASLSUCCESS | Response::ERR_SASLALREADY, _) => self.end_negotiation(),
+            Command::Response(Response::ERR_SASLFAIL | Response::ERR_SASLTOOLONG | Response::ERR_SASLABORTED | Response::ERR_NICKLOCKED, _) => {
+                if !self.negotiating {
+                    return;
+                }
+                let abort = self.config.as_ref()
+                    .and_then(|config| config.get_option("sasl_abort_on_failure"))
+                    .is_some_and(|abort| abort == "true");
+                if abort {
+                    self.server_status("SASL authentication failed, disconnecting");
+                    self.quit_with_reason("SASL authentication failed");
+                } else {
+                    self.server_status("SASL authentication failed, continuing without an account");
+                    self.end_negotiation();
+                }
+            },
+            _ => {},
+        }
+    }
+
+    /// Sends CAP REQ for the given capabilities.
+    fn request_caps(&mut self, request: Vec<String>) {
+        self.caps.requested(&request);
+        self.send(Command::CAP(None, CapSubCommand::REQ, None, Some(request.join(" "))));
+    }
+
+    /// Sends CAP END once, letting registration complete.
+    fn end_negotiation(&mut self) {
+        if self.negotiating {
+            self.negotiating = false;
+            self.send(Command::CAP(None, CapSubCommand::END, None, None));
+        }
+    }
+
+    /// Whether CHATHISTORY can be used on this connection.
+    fn history_enabled(&self) -> bool {
+        (self.caps.is_enabled("draft/chathistory") || self.caps.is_enabled("chathistory")) && self.caps.is_enabled("batch")
+    }
+
+    /// Messages to fetch per CHATHISTORY request.
+    fn history_limit(&self) -> usize {
+        let lines = self.config.as_ref()
+            .and_then(|config| config.get_option("history_lines"))
+            .and_then(|lines| lines.parse().ok())
+            .unwrap_or(DEFAULT_HISTORY_LINES);
+        match self.server_info.chathistory {
+            Some(max) if max > 0 => lines.min(max),
+            _ => lines,
+        }
+    }
+
+    /// Requests a page of history for a buffer: the latest messages, or with
+    /// `older` the ones before its oldest line.
+    ///
+    /// Without server-side history the page comes from the local log instead.
+    pub fn fetch_history(&mut self, target: &str, older: bool) {
+        if !self.history_enabled() {
+            self.load_backlog(target, older);
+            return;
+        }
+        let limit = self.history_limit();
+        let Some(buffer) = self.buffer_mut(target) else {
+            return;
+        };
+        if buffer.kind == BufferKind::Server || buffer.history_pending || (older && buffer.history_exhausted) {
+            return;
+        }
+        let command = match buffer.lines.first().filter(|_| older) {
+            Some(oldest) => history::before(buffer.name(), oldest, limit),
+            None => history::latest(buffer.name(), limit),
+        };
+        buffer.history_pending = true;
+        self.send(command);
+    }
+
+    /// Fills a buffer from its log: the latest lines when it is still empty,
+    /// followed by a separator, or with `older` the page before its oldest line.
+    fn load_backlog(&mut self, target: &str, older: bool) {
+        let Some(logger) = &self.logger else {
+            return;
+        };
+        let limit = self.config.as_ref()
+            .and_then(|config| config.get_option("backlog_lines"))
+            .and_then(|lines| lines.parse().ok())
+            .unwrap_or(DEFAULT_BACKLOG_LINES);
+        let network = self.network_name();
+        let Some(buffer) = self.buffer(target) else {
+            return;
+        };
+        if matches!(buffer.kind, BufferKind::Server | BufferKind::Search) || (older && buffer.history_exhausted) || (!older && !buffer.lines.is_empty()) {
+            return;
+        }
+        let before = buffer.lines.first().filter(|_| older).map(|oldest| oldest.time);
+        let lines = logger.backlog(&network, buffer.name(), before, limit);
+        let buffer = self.buffer_mut(target).unwrap();
+        if lines.len() < limit {
+            buffer.history_exhausted = true;
+        }
+        if lines.is_empty() {
+            return;
+        }
+        if !older {
+            buffer.lines.extend(lines);
+            buffer.lines.push(ChatLine::new(LineKind::Separator, None, Some(target), "end of saved log"));
+            return;
+        }
+        let added = buffer.merge_history(lines);
+        // Keep the view on the lines that were on screen before.
+        if !buffer.follow {
+            buffer.scroll = buffer.scroll.saturating_add(added as u16);
+        }
+    }
+
+    /// Collects messages of `chathistory` batches and merges each batch into
+    /// its buffer once complete. Returns whether the message was consumed.
+    fn collect_history(&mut self, message: &Message, line: &ChatLine) -> bool {
+        if let Some(batch) = line.tag("batch").and_then(|reference| self.history_batches.get_mut(reference)) {
+            batch.lines.push(line.clone());
+            return true;
+        }
+        match &message.command {
+            Command::BATCH(reference, kind, params) => {
+                if let Some(reference) = reference.strip_prefix('+') {
+                    let is_history = matches!(kind, Some(BatchSubCommand::CUSTOM(kind)) if kind.eq_ignore_ascii_case("chathistory"));
+                    let Some(target) = params.as_ref().and_then(|params| params.first()).filter(|_| is_history) else {
+                        return false;
+                    };
+                    self.history_batches.insert(reference.to_string(), HistoryBatch { target: target.clone(), lines: vec![] });
+                    return true;
+                }
+                let Some(batch) = reference.strip_prefix('-').and_then(|reference| self.history_batches.remove(reference)) else {
+                    return false;
+                };
+                let limit = self.history_limit();
+                let index = self.open_buffer(&batch.target);
+                let buffer = &mut self.buffers[index];
+                let received = batch.lines.len();
+                let added = buffer.merge_history(batch.lines);
+                buffer.history_pending = false;
+                buffer.history_exhausted = received < limit;
+                // Keep the view on the lines that were on screen before.
+                if !buffer.follow {
+                    buffer.scroll = buffer.scroll.saturating_add(added as u16);
+                }
+                true
+            },
+            Command::Raw(command, params) if command == "FAIL" && params.first().is_some_and(|command| command == "CHATHISTORY") => {
+                for buffer in self.buffers.iter_mut().filter(|buffer| buffer.history_pending) {
+                    buffer.history_pending = false;
+                    buffer.history_exhausted = true;
+                }
+                false
+            },
+            _ => false,
+        }
+    }
+
+    /// Scrolls the active buffer up, fetching older history at the top.
+    pub fn scroll_up(&mut self) {
+        if self.active().kind == BufferKind::Search {
+            self.select_result(false);
+            return;
+        }
+        self.active_mut().scroll_up();
+        if self.active().scroll == 0 {
+            let name = self.active_name();
+            self.fetch_history(&name, true);
+        }
+    }
+
+    /// Scrolls the active buffer down, or selects the next search result.
+    pub fn scroll_down(&mut self) {
+        if self.active().kind == BufferKind::Search {
+            self.select_result(true);
+            return;
+        }
+        self.active_mut().scroll_down();
+    }
+
+    /// Puts `/search ` on the input line.
+    pub fn start_search(&mut self) {
+        self.input = "/search ".to_string();
+        self.cursor_position = self.input.chars().count();
+    }
+
+    /// Enters [`Mode::Search`] with an empty query.
+    pub fn start_buffer_search(&mut self) {
+        self.mode = Mode::Search;
+        self.buffer_search = BufferSearch { editing: true, ..Default::default() };
+    }
+
+    /// Leaves [`Mode::Search`], keeping the view where it is.
+    pub fn stop_buffer_search(&mut self) {
+        self.mode = Mode::Normal;
+        self.buffer_search = BufferSearch::default();
+    }
+
+    /// Matches of the incremental search in the active buffer.
+    pub fn buffer_search_matches(&self) -> Vec<(usize, std::ops::Range<usize>)> {
+        if self.buffer_search.query.is_empty() {
+            return vec![];
+        }
+        let texts: Vec<String> = self.active().lines.iter().map(|line| format::strip(&ui::format_line(line))).collect();
+        self.buffer_search.matches(texts.iter().map(|text| text.as_str()))
+    }
+
+    /// Edits the search query and selects the most recent match.
+    pub fn edit_buffer_search(&mut self, c: Option<char>) {
+        match c {
+            Some(c) => self.buffer_search.query.push(c),
+            None => {
+                self.buffer_search.query.pop();
+            },
+        }
+        let matches = self.buffer_search_matches();
+        self.buffer_search.select_last(&matches);
+        self.show_search_match();
+    }
+
+    /// Moves to the previous match, or with `newer` the next one.
+    pub fn step_buffer_search(&mut self, newer: bool) {
+        let matches = self.buffer_search_matches();
+        self.buffer_search.step(&matches, newer);
+        self.show_search_match();
+    }
+
+    fn show_search_match(&mut self) {
+        if let Some((line, _)) = self.buffer_search.current.clone() {
+            let buffer = self.active_mut();
+            buffer.jump_to = Some(line);
+            buffer.follow = false;
+        }
+    }
+
+    /// Runs a search and lists the matching lines in the search buffer, oldest first.
+    fn search(&mut self, input: &str) {
+        let query = match Query::parse(input) {
+            Ok(query) => query,
+            Err(err) => {
+                self.status(format!("Error: {}", err));
+                return;
+            }
+        };
+        let mut hits: Vec<ChatLine> = vec![];
+        let mut add_hits = |buffer: &str, lines: &[ChatLine], server_info: &ServerInfo| {
+            hits.extend(lines.iter()
+                .filter(|line| line.kind != LineKind::Separator && query.matches(line, server_info))
+                .map(|line| ChatLine { target: Some(buffer.to_string()), ..line.clone() }));
+        };
+        match query.scope {
+            Scope::Buffer => {
+                let buffer = self.active();
+                if buffer.kind == BufferKind::Search {
+                    self.status("Error: Select a buffer to search, or use -all or -logs");
+                    return;
+                }
+                add_hits(buffer.name(), &buffer.lines, &self.server_info);
+            },
+            Scope::Open => {
+                for buffer in self.buffers.iter().filter(|buffer| buffer.kind != BufferKind::Search) {
+                    add_hits(buffer.name(), &buffer.lines, &self.server_info);
+                }
+            },
+            Scope::Logs => {
+                let Some(logger) = &self.logger else {
+                    self.status("Error: Logging is turned off");
+                    return;
+                };
+                for (stem, path) in logger.network_files(&self.network_name()) {
+                    // File names are sanitized, so prefer the target a line was logged
+                    // with, then an open buffer writing to the same file.
+                    let file_buffer = self.buffers.iter()
+                        .find(|buffer| logger::file_name(buffer.name()) == stem)
+                        .map_or(stem, |buffer| buffer.name().to_string());
+                    for line in logger.read(&path) {
+                        let buffer = self.logged_buffer(&line).unwrap_or_else(|| file_buffer.clone());
+                        add_hits(&buffer, std::slice::from_ref(&line), &self.server_info);
+                    }
+                }
+            },
+        }
+        hits.sort_by_key(|line| line.time);
+        let found = hits.len();
+        // Keep the most recent matches.
+        let hits = hits.split_off(found.saturating_sub(MAX_RESULTS));
+        let index = self.open_buffer(SEARCH_BUFFER);
+        let buffer = &mut self.buffers[index];
+        buffer.topic = Some(match found > hits.len() {
+            true => format!("latest {} of {} matches for {}", hits.len(), found, input.trim()),
+            false => format!("{} matches for {}", found, input.trim()),
+        });
+        buffer.selected = hits.len().saturating_sub(1);
+        buffer.jump_to = Some(buffer.selected);
+        buffer.follow = false;
+        buffer.lines = hits;
+        self.select_tab(index);
+        if found == 0 {
+            self.status("No matches");
+        }
+    }
+
+    /// Buffer a logged line belongs to, from the target it was logged with:
+    /// the sender for private messages to us, the target otherwise.
+    fn logged_buffer(&self, line: &ChatLine) -> Option<String> {
+        let target = line.target.as_deref()?;
+        match self.is_me(target) {
+            true => line.sender.clone(),
+            false => Some(target.to_string()),
+        }
+    }
+
+    /// Moves the selection of the search buffer.
+    fn select_result(&mut self, next: bool) {
+        let buffer = self.active_mut();
+        buffer.selected = match next {
+            true => (buffer.selected + 1).min(buffer.lines.len().saturating_sub(1)),
+            false => buffer.selected.saturating_sub(1),
+        };
+        buffer.jump_to = Some(buffer.selected);
+    }
+
+    /// Opens the buffer of the selected search result and shows the line in
+    /// context, loading older log pages until it is found.
+    pub fn open_result(&mut self) {
+        let results = self.active();
+        let Some(hit) = results.lines.get(results.selected).cloned() else {
+            return;
+        };
+        let Some(target) = hit.target.clone() else {
+            return;
+        };
+        let index = self.open_buffer(&target);
+        // Logs keep whole seconds only.
+        let same_time = |line: &ChatLine| line.time.timestamp() == hit.time.timestamp();
+        loop {
+            let buffer = &self.buffers[index];
+            if buffer.lines.first().is_some_and(|oldest| oldest.time.timestamp() <= hit.time.timestamp()) || buffer.history_exhausted {
+                break;
+            }
+            let loaded = buffer.lines.len();
+            self.load_backlog(&target, loaded > 0);
+            if self.buffers[index].lines.len() == loaded {
+                break;
+            }
+        }
+        let buffer = &mut self.buffers[index];
+        let position = buffer.lines.iter().position(|line| same_time(line) && line.text == hit.text)
+            .unwrap_or_else(|| buffer.lines.partition_point(|line| line.time.timestamp() < hit.time.timestamp()));
+        buffer.follow = false;
+        buffer.jump_to = Some(position.min(buffer.lines.len().saturating_sub(1)));
+        self.select_tab(index);
+    }
 
+    /// Matches an echo of our own message, or an error about it, against the
+    /// pending local line. Returns whether the echo took the pending line's place.
+    fn reconcile(&mut self, message: &Message, line: &ChatLine) -> bool {
+        if let Command::BATCH(reference, _, _) = &message.command {
+            match (reference.strip_prefix('+'), line.tag("label")) {
+                (Some(reference), Some(label)) => {
+                    self.label_batches.insert(reference.to_string(), label.to_string());
+                },
+                _ => {
+                    self.label_batches.remove(reference.trim_start_matches('-'));
+                },
+            }
+            return false;
+        }
+        // Replies to a labeled command that span several messages carry the label on their batch.
+        let label = line.tag("label")
+            .or_else(|| line.tag("batch").and_then(|reference| self.label_batches.get(reference)).map(|label| label.as_str()))
+            .map(|label| label.to_string());
+        let own_echo = matches!(line.kind, LineKind::Privmsg | LineKind::Notice | LineKind::Action)
+            && line.sender.as_deref().is_some_and(|sender| self.is_me(sender));
+        if own_echo {
+            let server_info = &self.server_info;
+            // The pending line may sit in the active buffer when the target had none open.
+            let pending = self.buffers.iter_mut()
+                .flat_map(|buffer| {
+                    let name = buffer.name().to_string();
+                    buffer.lines.iter_mut().map(move |pending| (name.clone(), pending))
+                })
+                .find(|(_, pending)| pending.delivery == Delivery::Pending
+                    // A send to several targets shares one label, with a line per target.
+                    && server_info.eq(pending.target.as_deref().unwrap_or(""), line.target.as_deref().unwrap_or(""))
+                    && match &label {
+                        Some(label) => pending.tag("label") == Some(label.as_str()),
+                        // Without labels the oldest pending line with the same text is ours.
+                        None => pending.kind == line.kind && pending.text == line.text,
+                    });
+            return match pending {
+                Some((buffer, pending)) => {
+                    *pending = line.clone();
+                    self.log_line(&buffer, line);
+                    true
+                },
+                None => false,
+            };
+        }
+        let Command::Response(response, args) = &message.command else {
+            return false;
+        };
+        let failed = match (&label, response) {
+            (Some(label), _) => {
+                // Prefer the line of the target an error names when the label covers several.
+                let target = args.get(1).map(String::as_str).unwrap_or("");
+                let server_info = &self.server_info;
+                let mut labeled: Vec<&mut ChatLine> = self.buffers.iter_mut()
+                    .flat_map(|buffer| buffer.lines.iter_mut())
+                    .filter(|pending| pending.delivery == Delivery::Pending && pending.tag("label") == Some(label.as_str()))
+                    .collect();
+                let index = labeled.iter().position(|pending| server_info.eq(pending.target.as_deref().unwrap_or(""), target)).unwrap_or(0);
+                (!labeled.is_empty()).then(|| labeled.swap_remove(index))
+            },
+            (None, Response::ERR_CANNOTSENDTOCHAN | Response::ERR_NOSUCHNICK | Response::ERR_NOSUCHCHANNEL) => {
+                let target = args.get(1).cloned().unwrap_or_default();
+                self.buffer_mut(&target)
+                    .and_then(|buffer| buffer.lines.iter_mut().find(|pending| pending.delivery == Delivery::Pending))
+            },
+            _ => None,
+        };
+        if let Some(line) = failed {
+            line.delivery = Delivery::Failed;
+        }
+        false
+    }
+
+    /// Whether a nick is our own.
+    fn is_me(&self, nick: &str) -> bool {
+        self.server_info.eq(nick, &self.username)
+    }
+
+    /// Whether we are in a channel, according to the joins and parts seen so far.
+    fn is_joined(&self, channel: &str) -> bool {
+        self.buffer(channel).is_some_and(|buffer| buffer.joined)
+    }
+
+    /// Picks the buffers a message is shown in.
+    ///
+    /// QUIT and NICK are shown in every channel shared with the user and in
+    /// their query, so this runs before the membership is updated.
+    fn route(&mut self, message: &Message, line: &ChatLine) -> Vec<String> {
+        let sender = match &message.prefix {
+            Some(Prefix::Nickname(nick, _, _)) => Some(nick.clone()),
+            _ => None,
+        };
+        match &message.command {
+            Command::PRIVMSG(target, _) | Command::NOTICE(target, _) => {
+                if self.server_info.is_channel(target) {
+                    vec![target.clone()]
                 } else {
-                    let message_content = message.to_string();
-                    self.messages.entry(channel.to_string(),).or_default().push(message_content);
-                    self.messaged.insert(channel.to_string());
-                    let tab_index = self.messaged.iter().position(|x| x == channel).unwrap();
-                    
+                    match sender {
+                        // Our own messages echoed back belong with their target.
+                        Some(sender) if self.is_me(&sender) => vec![target.clone()],
+                        Some(sender) => vec![sender],
+                        None => vec![SERVER_BUFFER.to_string()],
+                    }
+                }
+            },
+            Command::JOIN(channel, _, _) | Command::PART(channel, _) | Command::KICK(channel, _, _) | Command::TOPIC(channel, _) => {
+                vec![channel.clone()]
+            },
+            Command::ChannelMODE(target, _) if self.server_info.is_channel(target) => vec![target.clone()],
+            Command::QUIT(_) | Command::NICK(_) => {
+                let Some(nick) = sender else {
+                    return vec![SERVER_BUFFER.to_string()];
+                };
+                let nick_key = self.key(&nick);
+                let me = self.is_me(&nick);
+                // A query follows a nick change unless one is open for the new nick,
+                // so the line goes to the name it will have by then.
+                let renamed = match &message.command {
+                    Command::NICK(new_nick) if self.buffer(new_nick).is_none() => Some(new_nick.clone()),
+                    _ => None,
+                };
+                let mut buffers: Vec<String> = self.buffers.iter()
+                    .filter(|buffer| match buffer.kind {
+                        BufferKind::Channel => buffer.joined && (me || buffer.members.contains_key(&nick_key)),
+                        BufferKind::Query => buffer.key == nick_key,
+                        BufferKind::Server | BufferKind::Search => false,
+                    })
+                    .map(|buffer| match (buffer.kind, &renamed) {
+                        (BufferKind::Query, Some(new_nick)) => new_nick.clone(),
+                        _ => buffer.name().to_string(),
+                    })
+                    .collect();
+                if buffers.is_empty() {
+                    buffers.push(SERVER_BUFFER.to_string());
                 }
+                buffers
+            },
+            Command::Response(_, _) => match &line.target {
+                Some(channel) if self.is_joined(channel) => vec![channel.clone()],
+                _ => vec![SERVER_BUFFER.to_string()],
+            },
+            _ => vec![SERVER_BUFFER.to_string()],
+        }
+    }
+
+    /// Keeps members, prefixes and topics of every joined channel up to date.
+    fn track_membership(&mut self, message: &Message) {
+        let sender = message.source_nickname().unwrap_or("").to_string();
+        let casemapping = self.server_info.casemapping;
+        match &message.command {
+            Command::JOIN(channel, _, _) => {
+                if self.is_me(&sender) {
+                    let index = self.open_buffer(channel);
+                    let buffer = &mut self.buffers[index];
+                    buffer.joined = true;
+                    buffer.members.clear();
+                    self.fetch_history(channel, false);
+                }
+                if let Some(buffer) = self.buffer_mut(channel) {
+                    buffer.members.insert(BufferKey::new(&sender, casemapping), String::new());
+                }
+            },
+            Command::PART(channel, _) => self.remove_member(channel, &sender),
+            Command::KICK(channel, nick, _) => self.remove_member(channel, &nick.clone()),
+            Command::QUIT(_) => {
+                let nick = self.key(&sender);
+                for buffer in self.buffers.iter_mut() {
+                    buffer.members.remove(&nick);
+                }
+            },
+            Command::NICK(new_nick) => {
+                if self.is_me(&sender) {
+                    self.username = new_nick.clone();
+                    let primary = self.primary_nick();
+                    if self.is_me(&primary) && self.server_info.monitor.is_some() {
+                        self.send(Command::Raw("MONITOR".to_string(), vec!["-".to_string(), primary]));
+                    }
+                }
+                let old = self.key(&sender);
+                let new = self.key(new_nick);
+                for buffer in self.buffers.iter_mut() {
+                    if let Some(prefixes) = buffer.members.remove(&old) {
+                        buffer.members.insert(new.clone(), prefixes);
+                    }
+                }
+                // Follow the user with their query, unless one is already open for the new nick.
+                if self.buffer(new_nick).is_none() {
+                    if let Some(buffer) = self.buffers.iter_mut().find(|buffer| buffer.kind == BufferKind::Query && buffer.key == old) {
+                        buffer.key = new;
+                    }
+                }
+            },
+            Command::ChannelMODE(channel, _) => {
+                let params = command_params(&message.command);
+                let changes = self.server_info.mode_changes(&params[2.min(params.len())..]);
+                let server_info = self.server_info.clone();
+                let Some(buffer) = self.buffer_mut(channel) else {
+                    return;
+                };
+                for change in changes {
+                    let (Some(prefix), Some(nick)) = (server_info.prefix_for(change.mode), change.param) else {
+                        continue;
+                    };
+                    if let Some(prefixes) = buffer.members.get_mut(&BufferKey::new(&nick, casemapping)) {
+                        prefixes.retain(|c| c != prefix);
+                        if change.adding {
+                            prefixes.push(prefix);
+                            let mut sorted: Vec<char> = prefixes.chars().collect();
+                            sorted.sort_by_key(|c| server_info.prefixes.iter().position(|(_, p)| p == c));
+                            *prefixes = sorted.into_iter().collect();
+                        }
+                    }
+                }
+            },
+            Command::TOPIC(channel, topic) => {
+                if let Some(buffer) = self.buffer_mut(channel) {
+                    buffer.topic = topic.clone().filter(|topic| !topic.is_empty());
+                }
+            },
+            Command::Response(Response::RPL_TOPIC, args) if args.len() >= 3 => {
+                if let Some(buffer) = self.buffer_mut(&args[1]) {
+                    buffer.topic = Some(args[2].clone());
+                }
+            },
+            Command::Response(Response::RPL_NOTOPIC, args) if args.len() >= 2 => {
+                if let Some(buffer) = self.buffer_mut(&args[1]) {
+                    buffer.topic = None;
+                }
+            },
+            Command::Response(Response::RPL_NAMREPLY, args) if args.len() >= 4 => {
+                let members: Vec<(BufferKey, String)> = args[3].split_whitespace()
+                    .map(|entry| {
+                        // userhost-in-names sends `@nick!user@host`.
+                        let (prefixes, entry) = self.server_info.split_prefixes(entry);
+                        let nick = entry.split('!').next().unwrap_or(entry);
+                        (self.key(nick), prefixes.to_string())
+                    })
+                    .collect();
+                if let Some(buffer) = self.buffer_mut(&args[2]) {
+                    buffer.members.extend(members);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn remove_member(&mut self, channel: &str, nick: &str) {
+        let me = self.is_me(nick);
+        let nick = self.key(nick);
+        if let Some(buffer) = self.buffer_mut(channel) {
+            if me {
+                buffer.joined = false;
+                buffer.members.clear();
             } else {
-                let message_content = message.to_string();
-                self.messages.entry("!server".to_string()).or_default().push(message_content);
-                let tab_index = self.tab_titles.iter().position(|x| x == "!server").unwrap();
+                buffer.members.remove(&nick);
             }
         }
-        if let Some(_users) = self.client.as_mut().unwrap().list_users(&self.active_channel) {
-            self.get_active_channel_users();
+    }
+
+    /// Handles the tick event of the terminal.
+    ///
+    /// Returns whether the state changed and the interface needs redrawing.
+    pub fn tick(&mut self) -> bool {
+        let now = Instant::now();
+        match self.connection {
+            ConnectionState::Registering | ConnectionState::Connected if self.lag.timed_out(now) => {
+                self.disconnected(&format!("Ping timeout ({}s)", self.lag.timeout.as_secs()));
+                true
+            },
+            ConnectionState::Connected => {
+                if let Some(token) = self.lag.ping_due(now) {
+                    self.send(Command::PING(token, None));
+                }
+                // Keep the status bar counting while a PING goes unanswered.
+                self.lag.current(now) > self.lag.last
+            },
+            _ => false,
+        }
+    }
+
+    /// Builds the buffer key of a channel or nick under the server's case mapping.
+    pub fn key(&self, name: &str) -> BufferKey {
+        BufferKey::new(name, self.server_info.casemapping)
+    }
+
+    /// Index of the buffer with the given name.
+    pub fn buffer_index(&self, name: &str) -> Option<usize> {
+        let key = self.key(name);
+        self.buffers.iter().position(|buffer| buffer.key == key)
+    }
+
+    pub fn buffer(&self, name: &str) -> Option<&Buffer> {
+        self.buffer_index(name).map(|index| &self.buffers[index])
+    }
+
+    pub fn buffer_mut(&mut self, name: &str) -> Option<&mut Buffer> {
+        self.buffer_index(name).map(|index| &mut self.buffers[index])
+    }
+
+    /// The selected buffer.
+    pub fn active(&self) -> &Buffer {
+        &self.buffers[self.selected_tab]
+    }
+
+    pub fn active_mut(&mut self) -> &mut Buffer {
+        &mut self.buffers[self.selected_tab]
+    }
+
+    /// Name of the selected buffer.
+    pub fn active_name(&self) -> String {
+        self.active().name().to_string()
+    }
+
+    /// Returns the index of a buffer, opening it at the end of the tab list if needed.
+    pub fn open_buffer(&mut self, name: &str) -> usize {
+        if let Some(index) = self.buffer_index(name) {
+            return index;
+        }
+        let kind = if name == SERVER_BUFFER {
+            BufferKind::Server
+        } else if name == SEARCH_BUFFER {
+            BufferKind::Search
+        } else if self.server_info.is_channel(name) {
+            BufferKind::Channel
+        } else {
+            BufferKind::Query
+        };
+        let mut buffer = Buffer::new(kind, name, self.server_info.casemapping);
+        buffer.strip_formatting = self.strips_formatting(name);
+        self.buffers.push(buffer);
+        if kind == BufferKind::Query {
+            self.fetch_history(name, false);
+        }
+        self.buffers.len() - 1
+    }
+
+    /// Whether the `strip_formatting` option lists a buffer, or is `*`.
+    fn strips_formatting(&self, name: &str) -> bool {
+        self.config.as_ref()
+            .and_then(|config| config.get_option("strip_formatting"))
+            .is_some_and(|names| names.split([' ', ',']).any(|listed| listed == "*" || self.server_info.eq(listed, name)))
+    }
+
+    /// Appends a line to a buffer, opening it if needed.
+    pub fn push_line(&mut self, buffer: &str, line: ChatLine) {
+        let index = self.open_buffer(buffer);
+        let mention = colors::mentions(&format::strip(&line.text), &self.username, self.server_info.casemapping);
+        // Own messages are logged once the server confirms them.
+        if line.delivery != Delivery::Pending {
+            let name = self.buffers[index].name().to_string();
+            self.log_line(&name, &line);
+        }
+        let buffer = &mut self.buffers[index];
+        if index != self.selected_tab && matches!(line.kind, LineKind::Privmsg | LineKind::Notice | LineKind::Action) {
+            buffer.unread += 1;
+            if mention {
+                buffer.highlights += 1;
+            }
+        }
+        buffer.lines.push(line);
+    }
+
+    /// Writes a line to the log of a buffer, skipping client status lines
+    /// and buffers listed in the `log_exclude` option.
+    fn log_line(&self, buffer: &str, line: &ChatLine) {
+        let Some(logger) = &self.logger else {
+            return;
+        };
+        if matches!(line.kind, LineKind::Status | LineKind::Separator) || logger.exclude.iter().any(|excluded| self.server_info.eq(excluded, buffer)) {
+            return;
+        }
+        logger.log(&self.network_name(), buffer, line);
+    }
+
+    /// Network name from ISUPPORT, or the server address until it is known.
+    pub fn network_name(&self) -> String {
+        self.server_info.network.clone()
+            .or_else(|| self.config.as_ref().and_then(|config| config.server().ok()).map(|server| server.to_string()))
+            .unwrap_or_default()
+    }
+
+    /// Selects a buffer, keeping the input line of the previous one as its draft.
+    pub fn select_tab(&mut self, index: usize) {
+        if index >= self.buffers.len() {
+            return;
+        }
+        let input = std::mem::take(&mut self.input);
+        let cursor_position = self.cursor_position;
+        if let Some(previous) = self.buffers.get_mut(self.selected_tab) {
+            previous.draft = input;
+            previous.draft_cursor = cursor_position;
+        }
+        self.selected_tab = index;
+        self.restore_draft();
+    }
+
+    fn restore_draft(&mut self) {
+        let buffer = &mut self.buffers[self.selected_tab];
+        buffer.unread = 0;
+        buffer.highlights = 0;
+        self.input = std::mem::take(&mut buffer.draft);
+        self.cursor_position = buffer.draft_cursor;
+    }
+
+    /// Closes a buffer and selects its left neighbour if it was selected.
+    /// The server buffer cannot be closed.
+    pub fn close_buffer(&mut self, index: usize) {
+        if index == 0 || index >= self.buffers.len() {
+            return;
+        }
+        self.buffers.remove(index);
+        if self.selected_tab == index {
+            self.selected_tab = index - 1;
+            self.restore_draft();
+        } else if self.selected_tab > index {
+            self.selected_tab -= 1;
         }
+    }
 
-        if let Some(channels) = self.client.as_mut().unwrap().list_channels() {
-            self.tab_titles = channels;
-            self.tab_titles.append(&mut self.messaged.clone().iter().cloned().collect());
-            self.tab_titles.insert(0, "!server".to_string());
+    /// Re-folds every buffer key after the case mapping changed, merging
+    /// buffers that now fold to the same name.
+    fn refold_keys(&mut self) {
+        let casemapping = self.server_info.casemapping;
+        let selected = self.active_name();
+        let mut index = 0;
+        while index < self.buffers.len() {
+            self.buffers[index].key.refold(casemapping);
+            self.buffers[index].members = self.buffers[index].members.drain().map(|(mut nick, prefixes)| {
+                nick.refold(casemapping);
+                (nick, prefixes)
+            }).collect();
+            match self.buffers[..index].iter().position(|buffer| buffer.key == self.buffers[index].key) {
+                Some(first) => {
+                    let mut duplicate = self.buffers.remove(index);
+                    self.buffers[first].lines.append(&mut duplicate.lines);
+                },
+                None => index += 1,
+            }
         }
+        // The selected buffer may have moved or merged into an earlier one.
+        self.selected_tab = self.buffer_index(&selected).unwrap_or(0);
+        self.hosts.clear();
     }
 
     /// Set running to false to quit the application.
     pub fn quit(&mut self) {
-        self.client.as_mut().unwrap().send_quit("Goodbye").unwrap();
+        self.quit_with_reason("Goodbye");
+    }
+
+    /// Sends QUIT with the given reason and stops the application.
+    pub fn quit_with_reason(&mut self, reason: &str) {
+        if self.client.is_some() {
+            self.send(Command::QUIT(Some(reason.to_string())));
+        }
         self.running = false;
     }
 
@@ -128,10 +1379,122 @@ impl App {
         output
     }
 
-    pub fn send_message(&mut self) {
-        let message = self.get_input().unwrap();
-        let _result = self.client.as_mut().unwrap().send_privmsg(self.active_channel.clone(), message.clone());
-        self.messages.entry(self.active_channel.clone()).or_default().push(format!("{} : ", self.username.to_owned()) + message.as_str() + "\n");
+    /// Submits the input line, running it as a command when it starts with `/`.
+    pub fn submit_input(&mut self) {
+        let input = self.get_input().unwrap();
+        if input.is_empty() && self.active().kind == BufferKind::Search {
+            self.open_result();
+            return;
+        }
+        match command::parse_input(&input) {
+            Input::Command(line) => self.run_command(line),
+            Input::Text(text) => self.send_message(text),
+        }
+    }
+
+    pub fn send_message(&mut self, message: &str) {
+        if message.is_empty() {
+            return;
+        }
+        match self.active().kind {
+            BufferKind::Server => {
+                self.status("Error: Messages cannot be sent to the server buffer, try /quote");
+                return;
+            },
+            BufferKind::Search => {
+                self.status("Error: Messages cannot be sent to the search buffer, press Enter on a result to open it");
+                return;
+            },
+            _ => {},
+        }
+        let target = self.active_name();
+        self.send_text(&target, LineKind::Privmsg, message);
+    }
+
+    /// Sends a message, action or notice and shows it in the target's buffer.
+    /// A comma-separated target goes out as one command, shown as a line in
+    /// the buffer of each name.
+    ///
+    /// With echo-message each line stays pending until the server echoes it
+    /// back, matched by label when labeled-response is enabled as well.
+    fn send_text(&mut self, target: &str, kind: LineKind, text: &str) {
+        let command = match kind {
+            LineKind::Notice => Command::NOTICE(target.to_string(), text.to_string()),
+            LineKind::Action => Command::PRIVMSG(target.to_string(), format!("\x01ACTION {}\x01", text)),
+            _ => Command::PRIVMSG(target.to_string(), text.to_string()),
+        };
+        let mut message = Message::from(command);
+        let mut lines: Vec<ChatLine> = target.split(',')
+            .map(|name| ChatLine::new(kind, Some(&self.username), Some(name), text))
+            .collect();
+        if self.caps.is_enabled("echo-message") {
+            let mut label = None;
+            if self.caps.is_enabled("labeled-response") {
+                self.last_label += 1;
+                let tag = Tag("label".to_string(), Some(self.last_label.to_string()));
+                message.tags = Some(vec![tag.clone()]);
+                label = Some(tag);
+            }
+            for line in lines.iter_mut() {
+                line.delivery = Delivery::Pending;
+                line.tags.extend(label.clone());
+            }
+        }
+        self.send(message);
+        for line in lines {
+            let name = line.target.clone().unwrap_or_default();
+            self.echo(&name, line);
+        }
+    }
+
+    /// Sends a message to comma-separated targets, in as few lines as the
+    /// server's TARGMAX allows.
+    fn send_to_targets(&mut self, targets: &str, kind: LineKind, text: &str) {
+        let command = match kind {
+            LineKind::Notice => "NOTICE",
+            _ => "PRIVMSG",
+        };
+        let targets: Vec<&str> = targets.split(',').collect();
+        let max = self.server_info.max_targets(command).unwrap_or(targets.len()).max(1);
+        for batch in targets.chunks(max) {
+            self.send_text(&batch.join(","), kind, text);
+        }
+    }
+
+    /// Adds a client-generated status line to the active buffer, or to the
+    /// server buffer while search results are shown.
+    pub fn status(&mut self, text: impl Into<String>) {
+        let target = match self.active().kind {
+            BufferKind::Search => SERVER_BUFFER.to_string(),
+            _ => self.active_name(),
+        };
+        let line = ChatLine::new(LineKind::Status, None, Some(&target), text);
+        self.push_line(&target, line);
+    }
+
+    /// Adds a client-generated status line to every buffer.
+    pub fn broadcast_status(&mut self, text: impl Into<String>) {
+        let text = text.into();
+        for buffer in self.buffers.iter_mut().filter(|buffer| buffer.kind != BufferKind::Search) {
+            let line = ChatLine::new(LineKind::Status, None, Some(buffer.name()), text.clone());
+            buffer.lines.push(line);
+        }
+    }
+
+    /// Adds a client-generated status line to the server buffer.
+    pub fn server_status(&mut self, text: impl Into<String>) {
+        let line = ChatLine::new(LineKind::Status, None, None, text);
+        self.push_line(SERVER_BUFFER, line);
+    }
+
+    /// Echoes a line we sent into the buffer of its target, or the active
+    /// buffer when no buffer is open for the target.
+    fn echo(&mut self, target: &str, line: ChatLine) {
+        let buffer = match self.buffer(target) {
+            Some(buffer) => buffer.name().to_string(),
+            None => self.active_name(),
+        };
+        self.push_line(&buffer, line);
     }
     pub fn move_cursor_left(&mut self) {
         let cursor_moved_left = self.cursor_position.saturating_sub(1);
@@ -178,88 +1541,645 @@ impl App {
         self.cursor_position = 0;
     }
 
+    /// Runs the input line as a command, with or without the leading `/`.
     pub fn process_command(&mut self) {
-        let command = self.get_input().unwrap();
-        let command_vec: Vec<&str> = command.split_whitespace().collect();
-        match command_vec[0] {
-            "quit" | "q" => self.quit(),
-            "join" | "j "=> {
-                self.client.as_mut().unwrap().send_join(command_vec[1]).unwrap();
-                self.tab_titles.push(command_vec[1].to_string());
-                self.active_channel = command_vec[1].to_string();
-            },
-            "users" | "u" => {
+        let input = self.get_input().unwrap();
+        self.run_command(input.strip_prefix('/').unwrap_or(&input));
+    }
+
+    /// Checks a message target, showing why it was rejected.
+    fn valid_target(&mut self, target: &str) -> bool {
+        match command::check_target(target, &self.server_info, |name| self.buffer(name).is_some()) {
+            Ok(()) => true,
+            Err(err) => {
+                self.status(format!("Error: {}", err));
+                false
+            },
+        }
+    }
+
+    /// Splits `/mode` arguments into the target and the modes. The first word
+    /// is the target unless it is a mode string, which in a channel buffer
+    /// includes bare letters such as `b` to list bans. A lone target queries
+    /// its modes.
+    fn mode_args(&self, args: &[String]) -> (String, String) {
+        let in_channel = self.active().kind == BufferKind::Channel;
+        let modes_first = args.first().is_some_and(|first| match in_channel {
+            true => self.server_info.is_mode_string(first),
+            false => first.starts_with(['+', '-']),
+        });
+        match args.split_first() {
+            Some((first, rest)) if !modes_first => (first.clone(), rest.join(" ")),
+            _ => (self.active_name(), args.join(" ")),
+        }
+    }
+
+    /// Parses a command line against the registry and runs it, reporting
+    /// invalid input in the active buffer.
+    pub fn run_command(&mut self, line: &str) {
+        let (spec, args) = match command::parse(line, &self.server_info) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                self.status(format!("Error: {}", err));
+                if let Some(spec) = line.split_whitespace().next().and_then(command::find) {
+                    self.status(format!("Usage: {}", spec.usage()));
+                }
+                return;
+            }
+        };
+        match spec.name {
+            "help" => match args.first() {
+                Some(name) => match command::find(name.trim_start_matches('/')) {
+                    Some(spec) => {
+                        for line in spec.help() {
+                            self.status(line);
+                        }
+                    },
+                    None => self.status(format!("Unknown command: /{}", name)),
+                },
+                None => {
+                    let names: Vec<String> = command::COMMANDS.iter().map(|spec| format!("/{}", spec.name)).collect();
+                    self.status(format!("Commands: {}", names.join(" ")));
+                    self.status("Type /help <command> for details, or // to send a line starting with /.");
+                },
+            },
+            "quit" => match args.first() {
+                Some(reason) => self.quit_with_reason(&reason.clone()),
+                None => self.quit(),
+            },
+            "join" => {
+                let channel = args[0].clone();
+                self.send(Command::JOIN(channel.clone(), args.get(1).cloned(), None));
+                let index = self.open_buffer(&channel);
+                self.buffers[index].channel_key = args.get(1).cloned();
+                self.select_tab(index);
+            },
+            "cap" => match args.first().map(|subcommand| subcommand.to_ascii_lowercase()).as_deref() {
+                None | Some("list") => {
+                    let enabled: Vec<&str> = self.caps.enabled.iter().map(|cap| cap.as_str()).collect();
+                    self.status(format!("Enabled capabilities: {}", if enabled.is_empty() { "none".to_string() } else { enabled.join(" ") }));
+                },
+                Some("ls") => {
+                    let offered: Vec<String> = self.caps.available.iter()
+                        .map(|(cap, value)| {
+                            let marker = if self.caps.is_enabled(cap) { "*" } else { "" };
+                            match value.as_str() {
+                                "" => format!("{}{}", cap, marker),
+                                value => format!("{}={}{}", cap, value, marker),
+                            }
+                        })
+                        .collect();
+                    self.status(format!("Offered capabilities (* enabled): {}", offered.join(" ")));
+                },
+                Some("req") => match args.get(1) {
+                    Some(caps) => self.request_caps(caps.split_whitespace().map(|cap| cap.to_string()).collect()),
+                    None => self.status(format!("Usage: {}", spec.usage())),
+                },
+                Some(subcommand) => {
+                    self.status(format!("Error: Unknown subcommand: {}", subcommand));
+                    self.status(format!("Usage: {}", spec.usage()));
+                },
+            },
+            "search" => self.search(&args[0]),
+            "users" => {
                 self.show_users = !self.show_users;
             },
-            "msg" | "m" => {
-                let message = command_vec[2..].join(" ");
-                let _result = self.client.as_mut().unwrap().send_privmsg(command_vec[1].to_string(), message.clone());
-                self.messaged.insert(command_vec[1].to_string());
-                self.messages.entry(command_vec[1].to_string()).or_default().push(format!("{} : ", self.username.to_owned()) + message.as_str() + "\n");
-            },
-            "close" | "c" => {
-                if self.messaged.contains(&self.active_channel) { 
-                    self.tab_titles.remove(self.selected_tab);
-                    self.messaged.remove(&self.active_channel);
-                    self.selected_tab = 0;
-                } else {
-                    self.client.as_mut().unwrap().send_part(self.active_channel.clone()).unwrap();
-                    self.tab_titles.remove(self.selected_tab);
-                    self.active_channel = "!server".to_string();
-                    self.selected_tab = 0;
+            "msg" => {
+                if !self.valid_target(&args[0]) {
+                    return;
                 }
+                for target in args[0].split(',') {
+                    self.open_buffer(target);
+                }
+                self.send_to_targets(&args[0], LineKind::Privmsg, &args[1]);
+            },
+            "me" => {
+                if matches!(self.active().kind, BufferKind::Server | BufferKind::Search) {
+                    self.status("Error: /me needs a channel or query buffer");
+                    return;
+                }
+                let target = self.active_name();
+                self.send_text(&target, LineKind::Action, &args[0]);
+            },
+            "notice" => {
+                if !self.valid_target(&args[0]) {
+                    return;
+                }
+                self.send_to_targets(&args[0], LineKind::Notice, &args[1]);
+            },
+            "nick" => {
+                if let Some(nicklen) = self.server_info.nicklen.filter(|nicklen| args[0].len() > *nicklen) {
+                    self.status(format!("Error: Nicknames are limited to {} characters", nicklen));
+                    return;
+                }
+                self.send(Command::NICK(args[0].clone()));
+                self.status(format!("Changing nickname to {}", args[0]));
+            },
+            "topic" => {
+                let Some((channel, rest)) = self.split_channel(spec.name, &args) else {
+                    return;
+                };
+                // An empty topic asks the server for the current one.
+                self.send(Command::TOPIC(channel, rest.first().cloned()));
+            },
+            "part" => {
+                let Some((channel, rest)) = self.split_channel(spec.name, &args) else {
+                    return;
+                };
+                self.send(Command::PART(channel.clone(), rest.first().cloned()));
+                if let Some(index) = self.buffer_index(&channel) {
+                    self.close_buffer(index);
+                }
+            },
+            "quote" => match args[0].parse::<Message>() {
+                Ok(message) => {
+                    self.send(message);
+                    let line = ChatLine::new(LineKind::Status, None, None, format!("-> {}", args[0]));
+                    self.push_line(SERVER_BUFFER, line);
+                },
+                Err(err) => self.status(format!("Error: Could not parse line: {}", err)),
+            },
+            "mode" => {
+                let (target, modes) = self.mode_args(&args);
+                if target == SERVER_BUFFER {
+                    self.status("Error: /mode needs a target outside the server buffer");
+                    return;
+                }
+                if !self.valid_target(&target) {
+                    return;
+                }
+                let mut params = vec![target];
+                params.extend(modes.split_whitespace().map(|param| param.to_string()));
+                self.send(Command::Raw("MODE".to_string(), params));
+            },
+            "op" | "deop" | "voice" | "devoice" => {
+                let Some((channel, rest)) = self.split_channel(spec.name, &args) else {
+                    return;
+                };
+                let (sign, mode) = match spec.name {
+                    "op" => ('+', 'o'),
+                    "deop" => ('-', 'o'),
+                    "voice" => ('+', 'v'),
+                    _ => ('-', 'v'),
+                };
+                let nicks: Vec<String> = rest[0].split_whitespace().map(|nick| nick.to_string()).collect();
+                self.send_modes(&channel, sign, mode, &nicks);
+            },
+            "ban" | "unban" => {
+                let Some((channel, rest)) = self.split_channel(spec.name, &args) else {
+                    return;
+                };
+                let sign = if spec.name == "ban" { '+' } else { '-' };
+                let masks: Vec<String> = rest[0].split_whitespace().map(|arg| self.mask_for(arg)).collect();
+                self.send_modes(&channel, sign, 'b', &masks);
+            },
+            "kick" | "kickban" => {
+                let Some((channel, rest)) = self.split_channel(spec.name, &args) else {
+                    return;
+                };
+                let nick = rest[0].clone();
+                if spec.name == "kickban" {
+                    let mask = self.mask_for(&nick);
+                    self.send_modes(&channel, '+', 'b', &[mask]);
+                }
+                let reason = rest.get(1).cloned().unwrap_or_else(|| self.username.clone());
+                self.send(Command::KICK(channel, nick, Some(reason)));
+            },
+            "invite" => {
+                let channel = args.get(1).cloned().unwrap_or_else(|| self.active_name());
+                if !self.server_info.is_channel(&channel) {
+                    self.status("Error: /invite needs a channel");
+                    return;
+                }
+                self.send(Command::INVITE(args[0].clone(), channel.clone()));
+                self.status(format!("Inviting {} to {}", args[0], channel));
+            },
+            "close" => match self.active().kind {
+                BufferKind::Server => self.status("Error: The server buffer cannot be closed"),
+                BufferKind::Query | BufferKind::Search => self.close_buffer(self.selected_tab),
+                BufferKind::Channel => {
+                    if self.active().joined {
+                        let channel = self.active_name();
+                        self.send(Command::PART(channel, None));
+                    }
+                    self.close_buffer(self.selected_tab);
+                },
             },
             _ => {}
         }
     }
 
-    pub fn get_active_channel_users(&mut self) {
-        self.active_channel_users.clear();
-        let users = self.client.as_ref().unwrap().list_users(self.active_channel.clone().as_str()).unwrap();
-        for user in users {
-            self.active_channel_users.push(user.get_nickname().to_string());
+    /// Takes the leading channel argument of a command, falling back to the
+    /// active buffer. Reports an error and returns `None` when neither is a channel.
+    fn split_channel(&mut self, name: &str, args: &[String]) -> Option<(String, Vec<String>)> {
+        match args.first() {
+            Some(first) if self.server_info.is_channel(first) => Some((first.clone(), args[1..].to_vec())),
+            _ if self.active().kind == BufferKind::Channel => Some((self.active_name(), args.to_vec())),
+            _ => {
+                self.status(format!("Error: /{} needs a channel", name));
+                None
+            }
         }
     }
 
-    pub fn next_tab(&mut self) {
-        if self.selected_tab.saturating_add(1)  < self.tab_titles.len() {
-            self.selected_tab = self.selected_tab.saturating_add(1);
+    /// strftime-style format of the timestamp column, from the `timestamp_format`
+    /// option. An empty format hides the column.
+    pub fn timestamp_format(&self) -> String {
+        let format = self.config.as_ref()
+            .and_then(|config| config.get_option("timestamp_format"))
+            .unwrap_or(DEFAULT_TIMESTAMP_FORMAT);
+        // chrono panics when asked to render an invalid specifier.
+        if StrftimeItems::new(format).any(|item| item == Item::Error) {
+            return DEFAULT_TIMESTAMP_FORMAT.to_string();
         }
-        self.active_channel = self.tab_titles[self.selected_tab].clone();
+        format.to_string()
     }
 
-    pub fn prev_tab(&mut self) {
-        self.selected_tab = self.selected_tab.saturating_sub(1);
-        self.active_channel = self.tab_titles[self.selected_tab].clone();
+    /// Turns a nick into a ban mask using its cached user and host,
+    /// leaving masks untouched.
+    fn mask_for(&self, arg: &str) -> String {
+        if operator::is_mask(arg) {
+            return arg.to_string();
+        }
+        let style = self.config.as_ref()
+            .and_then(|config| config.get_option("ban_mask"))
+            .and_then(|style| style.parse::<BanMaskStyle>().ok())
+            .unwrap_or_default();
+        let userhost = self.hosts.get(&self.server_info.normalize(arg)).map(|(user, host)| (user.as_str(), host.as_str()));
+        operator::ban_mask(arg, userhost, style)
     }
 
-    pub fn sasl_plain(&mut self) -> AppResult<()> {
-        use irc::client::prelude::{Capability, Command};
-        let conf = self.config.as_mut().unwrap().clone();
-        self.client.as_mut().unwrap().send_cap_req(&[Capability::Sasl])?;
-        self.client.as_mut().unwrap().identify()?;
-        self.client.as_mut().unwrap().send_sasl_plain()?;
-        let sasl = format!("{}\x00{}\x00{}", &conf.nickname().unwrap(), &conf.nickname().unwrap(),  &conf.password());
-        let sasl = BASE64_STANDARD.encode(sasl);
-        self.client.as_mut().unwrap().send(Command::AUTHENTICATE(sasl))?;
-        self.client.as_mut().unwrap().send(Command::CAP(None, "END".parse()?, None, None))?;
-        Ok(())
+    /// Sends one mode for each parameter, batched by the server's MODES limit.
+    fn send_modes(&mut self, channel: &str, sign: char, mode: char, params: &[String]) {
+        for command in operator::batch_modes(channel, sign, mode, params, self.server_info.modes) {
+            self.send(command);
+        }
+    }
+
+    pub fn next_tab(&mut self) {
+        if self.selected_tab.saturating_add(1) < self.buffers.len() {
+            self.select_tab(self.selected_tab + 1);
+        }
+    }
+
+    pub fn prev_tab(&mut self) {
+        self.select_tab(self.selected_tab.saturating_sub(1));
     }
 
     pub fn process_config_options(&mut self) {
-        let opts = self.config.clone().unwrap().options;
-        for (key, value) in opts {
-            match key {
-                key if key.contains("on_join") => {
-                    let content = value.split_whitespace().collect::<Vec<&str>>();
-                    let recipient = content[0];
-                    let msg = content[1..].join(" ");
-                    let _ = self.client.as_mut().unwrap().send_privmsg(recipient, &msg);
-                    self.messaged.insert(recipient.to_string());
-                    self.messages.entry(recipient.to_string()).or_default().push(format!("{} : ", self.username.to_owned()) + msg.as_str() + "\n");
+        let Some(config) = &self.config else {
+            return;
+        };
+        let on_join: Vec<(String, String)> = config.options.iter()
+            .filter(|(key, _)| key.contains("on_join"))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        for (key, value) in on_join {
+            match value.trim().split_once(' ') {
+                Some((recipient, msg)) if !msg.trim().is_empty() => {
+                    self.open_buffer(recipient);
+                    self.send_text(recipient, LineKind::Privmsg, msg.trim());
                 },
-                _ => {}
+                _ => self.server_status(format!("Error: {} needs a target and a message, got {:?}", key, value)),
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isupport::CaseMapping;
+    use crate::logger::{LogFormat, Rotation};
+
+    fn app() -> App {
+        App { username: "me".to_string(), ..Default::default() }
+    }
+
+    fn from(nick: &str, command: Command) -> Message {
+        Message {
+            tags: None,
+            prefix: Some(Prefix::Nickname(nick.to_string(), "user".to_string(), "host".to_string())),
+            command,
+        }
+    }
+
+    fn numeric(response: Response, args: &[&str]) -> Message {
+        Message {
+            tags: None,
+            prefix: Some(Prefix::ServerName("irc.example.com".to_string())),
+            command: Command::Response(response, args.iter().map(|arg| arg.to_string()).collect()),
+        }
+    }
+
+    #[test]
+    fn parted_channel_stays_closed() {
+        let mut app = app();
+        app.handle_message(from("me", Command::JOIN("#rust".to_string(), None, None)));
+        assert_eq!(app.buffers.len(), 2);
+        app.run_command("part #rust");
+        assert_eq!(app.buffers.len(), 1);
+        app.handle_message(from("me", Command::PART("#rust".to_string(), None)));
+        app.handle_message(from("op", Command::KICK("#rust".to_string(), "me".to_string(), None)));
+        assert_eq!(app.buffers.len(), 1);
+    }
+
+    #[test]
+    fn message_targets_checked_on_send() {
+        let mut app = app();
+        app.run_command("msg *status help");
+        assert!(app.buffer("*status").is_some());
+        app.run_command("msg 4lice hi");
+        assert!(app.buffer("4lice").is_none());
+        assert_eq!(texts(&app, SERVER_BUFFER).last().map(String::as_str), Some("Error: Not a channel or nickname: 4lice"));
+    }
+
+    #[test]
+    fn multi_target_sends_follow_targmax() {
+        let mut app = app();
+        app.server_info.targmax.insert("PRIVMSG".to_string(), Some(2));
+        app.caps.ack("echo-message labeled-response");
+        app.run_command("msg a,b,c hi");
+        for name in ["a", "b", "c"] {
+            let lines = &app.buffer(name).unwrap().lines;
+            assert_eq!(lines.len(), 1, "{}", name);
+            assert_eq!((lines[0].target.as_deref(), lines[0].delivery), (Some(name), Delivery::Pending));
+        }
+        assert!(app.buffer(SERVER_BUFFER).unwrap().lines.iter().all(|line| line.kind != LineKind::Privmsg));
+        // Both lines of the first command share its label, each echo confirms its own.
+        assert_eq!(app.buffer("a").unwrap().lines[0].tag("label"), app.buffer("b").unwrap().lines[0].tag("label"));
+        app.handle_message(tagged(from("me", Command::PRIVMSG("b".to_string(), "hi".to_string())), "label", "1"));
+        assert_eq!(app.buffer("a").unwrap().lines[0].delivery, Delivery::Pending);
+        assert_eq!(app.buffer("b").unwrap().lines[0].delivery, Delivery::Confirmed);
+    }
+
+    #[test]
+    fn casemapping_change_keeps_selection() {
+        for (selected, expected) in [("#a{b}", "#a[b]"), ("#d", "#d")] {
+            let mut app = app();
+            app.server_info.casemapping = CaseMapping::Ascii;
+            for channel in ["#a[b]", "#c", "#a{b}", "#d"] {
+                join(&mut app, "me", channel);
+            }
+            app.selected_tab = app.buffer_index(selected).unwrap();
+            app.handle_message(numeric(Response::RPL_ISUPPORT, &["me", "CASEMAPPING=rfc1459", "are supported"]));
+            assert_eq!(app.buffers.len(), 4);
+            assert_eq!(app.active_name(), expected);
+        }
+    }
+
+    #[test]
+    fn malformed_on_join_is_reported() {
+        let mut config = Config::default();
+        config.options.insert("on_join".to_string(), "NickServ  hi there".to_string());
+        config.options.insert("on_join_empty".to_string(), String::new());
+        config.options.insert("on_join_alone".to_string(), "alice".to_string());
+        let mut app = App { config: Some(config), ..app() };
+        app.process_config_options();
+        assert_eq!(texts(&app, "NickServ"), vec!["hi there"]);
+        assert!(app.buffer("alice").is_none());
+        let errors = texts(&app, SERVER_BUFFER).into_iter().filter(|text| text.starts_with("Error: on_join")).count();
+        assert_eq!(errors, 2);
+    }
+
+    #[test]
+    fn regain_waits_for_identification() {
+        let mut config = Config { nickname: Some("me".to_string()), ..Default::default() };
+        config.options.insert("nick_regain".to_string(), "ghost".to_string());
+        let mut app = App { config: Some(config), username: "me_".to_string(), ..Default::default() };
+        let recovering = |app: &App| texts(app, SERVER_BUFFER).iter().filter(|text| text.starts_with("Recovering")).count();
+        app.handle_message(numeric(Response::RPL_WELCOME, &["me_", "Welcome"]));
+        app.handle_message(numeric(Response::RPL_ENDOFMOTD, &["me_", "End of MOTD"]));
+        assert_eq!(recovering(&app), 0);
+        app.handle_message(numeric(Response::RPL_LOGGEDIN, &["me_", "me_!u@h", "me", "You are now logged in"]));
+        app.handle_message(numeric(Response::RPL_SASLSUCCESS, &["me_", "SASL authentication successful"]));
+        assert_eq!(recovering(&app), 1);
+    }
+
+    #[test]
+    fn highlights_need_the_whole_nick() {
+        let mut app = app();
+        join(&mut app, "me", "#rust");
+        for text in ["some time", "hey ME: look"] {
+            app.handle_message(from("alice", Command::PRIVMSG("#rust".to_string(), text.to_string())));
+        }
+        assert_eq!(app.buffer("#rust").unwrap().highlights, 1);
+    }
+
+    #[tokio::test]
+    async fn log_results_keep_buffer_names() {
+        let dir = std::env::temp_dir().join(format!("ironirc-search-{}", std::process::id()));
+        let mut app = app();
+        app.open_buffer("Alice");
+        let line = ChatLine::new(LineKind::Privmsg, Some("bob"), Some("#Rust/Dev"), "found it");
+        for format in [LogFormat::Json, LogFormat::Text] {
+            let logger = Logger::new(dir.clone(), format, Rotation::Never, vec![]);
+            for buffer in ["#Rust/Dev", "Alice"] {
+                let path = logger.path(&app.network_name(), buffer);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(&path, logger::format_entry(format, &line) + "\n").unwrap();
+            }
+            app.logger = Some(logger);
+            app.run_command("search -logs found");
+            let mut targets: Vec<&str> = app.active().lines.iter().filter_map(|line| line.target.as_deref()).collect();
+            targets.sort();
+            match format {
+                LogFormat::Json => assert_eq!(targets, vec!["#Rust/Dev", "#Rust/Dev"]),
+                // Text logs keep no target, only the file name to go by.
+                LogFormat::Text => assert_eq!(targets, vec!["#rust_dev", "Alice"]),
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn mode_targets() {
+        let mut app = app();
+        let words = |line: &str| line.split_whitespace().map(|word| word.to_string()).collect::<Vec<_>>();
+        assert_eq!(app.mode_args(&words("b")), ("b".to_string(), String::new()));
+        join(&mut app, "me", "#rust");
+        app.selected_tab = app.buffer_index("#rust").unwrap();
+        for (line, target, modes) in [
+            ("b", "#rust", "b"),
+            ("+b", "#rust", "+b"),
+            ("b *!*@spam", "#rust", "b *!*@spam"),
+            ("alice", "alice", ""),
+            ("#other +m", "#other", "+m"),
+            ("", "#rust", ""),
+        ] {
+            assert_eq!(app.mode_args(&words(line)), (target.to_string(), modes.to_string()), "{}", line);
+        }
+    }
+
+    #[test]
+    fn seconds_options() {
+        let mut config = Config::default();
+        config.options.insert("ping_timeout".to_string(), "45".to_string());
+        config.options.insert("reconnect_delay".to_string(), "soon".to_string());
+        let app = App { config: Some(config), ..app() };
+        assert_eq!(app.lag_from_config().timeout, Duration::from_secs(45));
+        assert_eq!(app.lag_from_config().interval, Lag::default().interval);
+        assert_eq!(app.backoff_from_config().base, Backoff::default().base);
+    }
+
+    #[test]
+    fn search_results_stay_clean() {
+        let mut app = app();
+        app.run_command("search -all nothing");
+        assert_eq!(app.active().kind, BufferKind::Search);
+        app.run_command("search again");
+        assert!(app.active().lines.is_empty());
+        assert_eq!(texts(&app, SERVER_BUFFER).last().map(String::as_str), Some("Error: Select a buffer to search, or use -all or -logs"));
+    }
+
+    #[test]
+    fn nick_fallback_only_while_registering() {
+        let mut app = app();
+        app.connection = ConnectionState::Registering;
+        app.nick_candidates = vec!["me_".to_string(), "me__".to_string()];
+        app.nick_unavailable();
+        assert_eq!(app.username, "me_");
+        app.handle_message(numeric(Response::ERR_NICKCOLLISION, &["*", "me_", "Nickname collision"]));
+        assert_eq!(app.username, "me__");
+        app.nick_unavailable();
+        assert_eq!(app.username, "me__");
+
+        app.connection = ConnectionState::Connected;
+        app.nick_attempt = 0;
+        app.nick_unavailable();
+        assert_eq!(app.username, "me__");
+    }
+
+    fn join(app: &mut App, nick: &str, channel: &str) {
+        app.handle_message(from(nick, Command::JOIN(channel.to_string(), None, None)));
+    }
+
+    fn texts(app: &App, buffer: &str) -> Vec<String> {
+        app.buffer(buffer).unwrap().lines.iter().map(|line| line.text.clone()).collect()
+    }
+
+    fn last_kind(app: &App, buffer: &str) -> Option<LineKind> {
+        app.buffer(buffer).unwrap().lines.last().map(|line| line.kind)
+    }
+
+    #[test]
+    fn private_messages_open_queries() {
+        let mut app = app();
+        app.handle_message(from("Alice", Command::PRIVMSG("me".to_string(), "hi".to_string())));
+        assert_eq!(app.buffer("alice").unwrap().kind, BufferKind::Query);
+        assert_eq!(texts(&app, "alice"), vec!["hi"]);
+        // Our own message echoed back goes to the query with its target.
+        app.handle_message(from("me", Command::PRIVMSG("alice".to_string(), "hello".to_string())));
+        assert_eq!(texts(&app, "alice"), vec!["hi", "hello"]);
+        assert!(app.buffer("me").is_none());
+    }
+
+    #[test]
+    fn own_joins_parts_and_kicks() {
+        let mut app = app();
+        join(&mut app, "me", "#rust");
+        join(&mut app, "alice", "#rust");
+        let buffer = app.buffer("#rust").unwrap();
+        assert!(buffer.joined);
+        assert!(buffer.members.contains_key(&app.key("ALICE")));
+
+        app.handle_message(from("op", Command::KICK("#rust".to_string(), "me".to_string(), Some("bye".to_string()))));
+        let buffer = app.buffer("#rust").unwrap();
+        assert!(!buffer.joined);
+        assert!(buffer.members.is_empty());
+
+        join(&mut app, "me", "#rust");
+        app.handle_message(from("me", Command::PART("#rust".to_string(), None)));
+        assert!(!app.buffer("#rust").unwrap().joined);
+        assert_eq!(app.buffers.len(), 2);
+    }
+
+    #[test]
+    fn nick_changes_follow_the_user() {
+        let mut app = app();
+        for channel in ["#a", "#b", "#c"] {
+            join(&mut app, "me", channel);
+        }
+        join(&mut app, "alice", "#a");
+        join(&mut app, "alice", "#b");
+        app.handle_message(from("alice", Command::PRIVMSG("me".to_string(), "hi".to_string())));
+        app.handle_message(from("alice", Command::NICK("alicia".to_string())));
+        for buffer in ["#a", "#b", "alicia"] {
+            assert_eq!(texts(&app, buffer).last().map(|text| text.as_str()), Some("alicia"), "{}", buffer);
+        }
+        assert_eq!(last_kind(&app, "#c"), Some(LineKind::Join));
+        assert!(app.buffer("#a").unwrap().members.contains_key(&app.key("alicia")));
+        assert!(!app.buffer("#a").unwrap().members.contains_key(&app.key("alice")));
+        assert!(app.buffer("alice").is_none());
+
+        app.handle_message(from("me", Command::NICK("myself".to_string())));
+        assert_eq!(app.username, "myself");
+        for buffer in ["#a", "#b", "#c"] {
+            assert_eq!(texts(&app, buffer).last().map(|text| text.as_str()), Some("myself"), "{}", buffer);
+        }
+    }
+
+    #[test]
+    fn quits_fan_out_to_shared_buffers() {
+        let mut app = app();
+        join(&mut app, "me", "#a");
+        join(&mut app, "me", "#b");
+        join(&mut app, "bob", "#a");
+        app.handle_message(from("bob", Command::PRIVMSG("me".to_string(), "hi".to_string())));
+        app.handle_message(from("bob", Command::QUIT(Some("gone".to_string()))));
+        assert_eq!(texts(&app, "#a").last().map(|text| text.as_str()), Some("gone"));
+        assert_eq!(texts(&app, "bob").last().map(|text| text.as_str()), Some("gone"));
+        assert_eq!(last_kind(&app, "#b"), Some(LineKind::Join));
+        assert!(!app.buffer("#a").unwrap().members.contains_key(&app.key("bob")));
+        // A quit from nobody we share a buffer with lands in the server buffer.
+        app.handle_message(from("carol", Command::QUIT(None)));
+        assert_eq!(app.buffer(SERVER_BUFFER).unwrap().lines.last().and_then(|line| line.sender.as_deref()), Some("carol"));
+    }
+
+    fn pending(app: &mut App, text: &str, label: Option<&str>) {
+        let mut line = ChatLine::new(LineKind::Privmsg, Some("me"), Some("#rust"), text);
+        line.delivery = Delivery::Pending;
+        line.tags.extend(label.map(|label| Tag("label".to_string(), Some(label.to_string()))));
+        let index = app.open_buffer("#rust");
+        app.buffers[index].lines.push(line);
+    }
+
+    fn tagged(mut message: Message, key: &str, value: &str) -> Message {
+        message.tags.get_or_insert_with(Vec::new).push(Tag(key.to_string(), Some(value.to_string())));
+        message
+    }
+
+    fn deliveries(app: &App) -> Vec<Delivery> {
+        app.buffer("#rust").unwrap().lines.iter().map(|line| line.delivery).collect()
+    }
+
+    #[test]
+    fn echoes_confirm_pending_lines() {
+        let mut app = app();
+        pending(&mut app, "hi", None);
+        pending(&mut app, "hi", None);
+        pending(&mut app, "labeled", Some("7"));
+        app.handle_message(tagged(from("me", Command::PRIVMSG("#rust".to_string(), "labeled".to_string())), "label", "7"));
+        assert_eq!(deliveries(&app), vec![Delivery::Pending, Delivery::Pending, Delivery::Confirmed]);
+        // Without a label the oldest pending line with the same text is the echoed one.
+        app.handle_message(from("me", Command::PRIVMSG("#rust".to_string(), "hi".to_string())));
+        assert_eq!(deliveries(&app), vec![Delivery::Confirmed, Delivery::Pending, Delivery::Confirmed]);
+        assert_eq!(app.buffer("#rust").unwrap().lines.len(), 3);
+    }
+
+    #[test]
+    fn errors_fail_pending_lines() {
+        let mut app = app();
+        pending(&mut app, "first", None);
+        pending(&mut app, "second", Some("8"));
+        let batch = |reference: &str| from("server", Command::BATCH(reference.to_string(), Some(BatchSubCommand::CUSTOM("labeled-response".to_string())), None));
+        app.handle_message(tagged(batch("+b1"), "label", "8"));
+        app.handle_message(tagged(numeric(Response::ERR_CANNOTSENDTOCHAN, &["me", "#rust", "Cannot send to channel"]), "batch", "b1"));
+        app.handle_message(batch("-b1"));
+        assert_eq!(deliveries(&app)[..2], [Delivery::Pending, Delivery::Failed]);
+        assert!(app.label_batches.is_empty());
+
+        app.handle_message(numeric(Response::ERR_CANNOTSENDTOCHAN, &["me", "#rust", "Cannot send to channel"]));
+        assert_eq!(deliveries(&app)[..2], [Delivery::Failed, Delivery::Failed]);
+    }
+}
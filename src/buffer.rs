@@ -0,0 +1,281 @@
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use ratatui::widgets::ScrollbarState;
+
+use crate::isupport::{CaseMapping, ServerInfo};
+use crate::message::ChatLine;
+
+/// Name of the buffer holding server messages.
+pub const SERVER_BUFFER: &str = "!server";
+
+/// Name of the buffer listing search results.
+pub const SEARCH_BUFFER: &str = "!search";
+
+/// What a buffer is a conversation with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BufferKind {
+    Server,
+    Channel,
+    Query,
+    /// Results of the last search.
+    Search,
+}
+
+/// A conversation shown as a tab, with everything the interface keeps about it.
+#[derive(Debug)]
+pub struct Buffer {
+    pub kind: BufferKind,
+    pub key: BufferKey,
+    pub lines: Vec<ChatLine>,
+    /// First visible row, counted in wrapped rows.
+    pub scroll: u16,
+    /// Largest useful `scroll`, as of the last render.
+    pub max_scroll: u16,
+    /// Whether the view sticks to the newest line.
+    pub follow: bool,
+    pub scroll_state: ScrollbarState,
+    /// Messages that arrived while the buffer was not selected.
+    pub unread: usize,
+    /// Unread messages that mention our nick.
+    pub highlights: usize,
+    pub topic: Option<String>,
+    /// Whether we are currently in the channel.
+    pub joined: bool,
+    /// Channel members mapped to their membership prefixes, such as `@`.
+    pub members: HashMap<BufferKey, String>,
+    /// Input line kept while another buffer is selected.
+    pub draft: String,
+    pub draft_cursor: usize,
+    /// Whether a CHATHISTORY request is in flight.
+    pub history_pending: bool,
+    /// Whether the server, or the log, has no older history to give.
+    pub history_exhausted: bool,
+    /// Key the channel was joined with, reused when rejoining.
+    pub channel_key: Option<String>,
+    /// Selected line of the search buffer.
+    pub selected: usize,
+    /// Line to bring into view on the next draw.
+    pub jump_to: Option<usize>,
+    /// Whether mIRC formatting is shown as plain text.
+    pub strip_formatting: bool,
+}
+
+impl Buffer {
+    /// Constructs a new instance of [`Buffer`].
+    pub fn new(kind: BufferKind, name: &str, casemapping: CaseMapping) -> Self {
+        Self {
+            kind,
+            key: BufferKey::new(name, casemapping),
+            lines: vec![],
+            scroll: 0,
+            max_scroll: 0,
+            follow: true,
+            scroll_state: ScrollbarState::default(),
+            unread: 0,
+            highlights: 0,
+            topic: None,
+            joined: false,
+            members: HashMap::new(),
+            draft: String::new(),
+            draft_cursor: 0,
+            history_pending: false,
+            history_exhausted: false,
+            channel_key: None,
+            selected: 0,
+            jump_to: None,
+            strip_formatting: false,
+        }
+    }
+
+    /// The name as first spelled.
+    pub fn name(&self) -> &str {
+        self.key.name()
+    }
+
+    pub fn scroll_up(&mut self) {
+        self.scroll = self.scroll.min(self.max_scroll).saturating_sub(1);
+        self.follow = false;
+        self.scroll_state = self.scroll_state.position(self.scroll as usize);
+    }
+
+    pub fn scroll_down(&mut self) {
+        self.scroll = self.scroll.saturating_add(1).min(self.max_scroll);
+        self.follow = self.scroll >= self.max_scroll;
+        self.scroll_state = self.scroll_state.position(self.scroll as usize);
+    }
+
+    /// Merges fetched history into the scrollback in time order, skipping
+    /// lines whose `msgid` is already known. Returns how many lines were added.
+    pub fn merge_history(&mut self, lines: Vec<ChatLine>) -> usize {
+        let mut known: HashSet<String> = self.lines.iter().filter_map(|line| line.tag("msgid")).map(|msgid| msgid.to_string()).collect();
+        let mut added = 0;
+        for line in lines {
+            if let Some(msgid) = line.tag("msgid") {
+                if !known.insert(msgid.to_string()) {
+                    continue;
+                }
+            }
+            let index = self.lines.partition_point(|existing| existing.time <= line.time);
+            self.lines.insert(index, line);
+            added += 1;
+        }
+        added
+    }
+
+    /// Member nicks with their highest prefix, ordered by rank and then by name.
+    pub fn nick_list(&self, server_info: &ServerInfo) -> Vec<String> {
+        let rank = |prefixes: &str| {
+            prefixes.chars()
+                .filter_map(|c| server_info.prefixes.iter().position(|(_, prefix)| *prefix == c))
+                .min()
+                .unwrap_or(server_info.prefixes.len())
+        };
+        let mut members: Vec<(&BufferKey, &String)> = self.members.iter().collect();
+        members.sort_by(|(a_nick, a_prefixes), (b_nick, b_prefixes)| {
+            rank(a_prefixes).cmp(&rank(b_prefixes)).then_with(|| a_nick.folded().cmp(b_nick.folded()))
+        });
+        members.into_iter()
+            .map(|(nick, prefixes)| format!("{}{}", prefixes.chars().next().map(String::from).unwrap_or_default(), nick.name()))
+            .collect()
+    }
+}
+
+/// Key identifying a buffer by its case-folded name.
+///
+/// Keys compare and hash by the folded name only, so `#Rust` and `#rust`
+/// share a buffer, while [`BufferKey::name`] keeps the spelling the buffer
+/// was first opened with.
+#[derive(Clone, Debug)]
+pub struct BufferKey {
+    name: String,
+    folded: String,
+}
+
+impl BufferKey {
+    /// Constructs a new instance of [`BufferKey`].
+    pub fn new(name: &str, casemapping: CaseMapping) -> Self {
+        Self {
+            name: name.to_string(),
+            folded: casemapping.normalize(name),
+        }
+    }
+
+    /// The name as first spelled.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The name folded under the case mapping the key was built with.
+    pub fn folded(&self) -> &str {
+        &self.folded
+    }
+
+    /// Re-folds the name after the server announced a different case mapping.
+    pub fn refold(&mut self, casemapping: CaseMapping) {
+        self.folded = casemapping.normalize(&self.name);
+    }
+}
+
+impl PartialEq for BufferKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.folded == other.folded
+    }
+}
+
+impl Eq for BufferKey {}
+
+impl Hash for BufferKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.folded.hash(state);
+    }
+}
+
+/// Lets maps keyed by [`BufferKey`] be queried with an already folded `&str`.
+impl Borrow<str> for BufferKey {
+    fn borrow(&self) -> &str {
+        &self.folded
+    }
+}
+
+impl fmt::Display for BufferKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn keys_fold_but_keep_spelling() {
+        let mut buffers = HashMap::new();
+        buffers.insert(BufferKey::new("#Rust[dev]", CaseMapping::Rfc1459), 1);
+        *buffers.entry(BufferKey::new("#rust{DEV}", CaseMapping::Rfc1459)).or_insert(0) += 1;
+        assert_eq!(buffers.len(), 1);
+        let (key, count) = buffers.iter().next().unwrap();
+        assert_eq!(key.name(), "#Rust[dev]");
+        assert_eq!(*count, 2);
+        assert_eq!(buffers.get("#rust{dev}"), Some(&2));
+    }
+
+    #[test]
+    fn refold_follows_casemapping() {
+        let mut key = BufferKey::new("Alice[m]", CaseMapping::Rfc1459);
+        assert_eq!(key.folded(), "alice{m}");
+        key.refold(CaseMapping::Ascii);
+        assert_eq!(key.folded(), "alice[m]");
+        assert_eq!(key.to_string(), "Alice[m]");
+    }
+
+    #[test]
+    fn nick_list_orders_by_rank() {
+        let info = ServerInfo::default();
+        let mut buffer = Buffer::new(BufferKind::Channel, "#rust", info.casemapping);
+        for (nick, prefixes) in [("zed", "@+"), ("Bob", ""), ("amy", "+"), ("carl", "@")] {
+            buffer.members.insert(BufferKey::new(nick, info.casemapping), prefixes.to_string());
+        }
+        assert_eq!(buffer.nick_list(&info), vec!["@carl", "@zed", "+amy", "Bob"]);
+    }
+
+    #[test]
+    fn scrolling_stops_following() {
+        let mut buffer = Buffer::new(BufferKind::Query, "bob", CaseMapping::Rfc1459);
+        buffer.max_scroll = 5;
+        buffer.scroll = 5;
+        buffer.scroll_up();
+        assert_eq!(buffer.scroll, 4);
+        assert!(!buffer.follow);
+        buffer.scroll_down();
+        buffer.scroll_down();
+        assert_eq!(buffer.scroll, 5);
+        assert!(buffer.follow);
+    }
+
+    #[test]
+    fn history_merges_in_order_without_duplicates() {
+        use crate::message::LineKind;
+        use chrono::{Duration, Local};
+        use irc::proto::Tag;
+
+        let now = Local::now();
+        let line = |text: &str, minutes: i64, msgid: Option<&str>| {
+            let mut line = ChatLine::new(LineKind::Privmsg, Some("alice"), Some("#rust"), text);
+            line.time = now - Duration::minutes(minutes);
+            if let Some(msgid) = msgid {
+                line.tags.push(Tag("msgid".to_string(), Some(msgid.to_string())));
+            }
+            line
+        };
+        let mut buffer = Buffer::new(BufferKind::Channel, "#rust", CaseMapping::Rfc1459);
+        buffer.lines.push(line("live", 1, Some("c")));
+        let added = buffer.merge_history(vec![line("old", 10, Some("a")), line("older", 20, Some("b")), line("live", 1, Some("c"))]);
+        assert_eq!(added, 2);
+        let texts: Vec<&str> = buffer.lines.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(texts, vec!["older", "old", "live"]);
+    }
+}
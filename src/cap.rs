@@ -0,0 +1,207 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Capabilities requested when the server offers them, unless the
+/// `capabilities` option says otherwise.
+pub const DEFAULT_CAPABILITIES: &[&str] = &[
+    "multi-prefix",
+    "server-time",
+    "message-tags",
+    "echo-message",
+    "away-notify",
+    "account-notify",
+    "extended-join",
+    "chghost",
+    "setname",
+    "batch",
+    "labeled-response",
+    "invite-notify",
+    "userhost-in-names",
+    "draft/chathistory",
+];
+
+/// IRCv3 capability negotiation state.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Capabilities to request whenever the server offers them.
+    pub wanted: Vec<String>,
+    /// Offered by the server, mapped to their values (empty when none).
+    pub available: BTreeMap<String, String>,
+    /// Acknowledged by the server and in effect.
+    pub enabled: BTreeSet<String>,
+    /// Requested and waiting for ACK or NAK.
+    pub pending: BTreeSet<String>,
+    /// Whether a multi-line CAP LS reply is still arriving.
+    listing: bool,
+}
+
+impl Capabilities {
+    /// Constructs a new instance of [`Capabilities`].
+    pub fn new(wanted: &[&str]) -> Self {
+        Self {
+            wanted: wanted.iter().map(|cap| cap.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Builds the wanted set from the `capabilities` option, a space or
+    /// comma separated list. `-name` drops a default and `+name` adds to them.
+    pub fn from_option(option: Option<&str>) -> Self {
+        let mut capabilities = Self::new(DEFAULT_CAPABILITIES);
+        let Some(option) = option else {
+            return capabilities;
+        };
+        let entries: Vec<&str> = option.split([' ', ',']).filter(|entry| !entry.is_empty()).collect();
+        if entries.iter().all(|entry| !entry.starts_with(['+', '-'])) {
+            capabilities.wanted.clear();
+        }
+        for entry in entries {
+            match entry.strip_prefix('-') {
+                Some(name) => capabilities.wanted.retain(|cap| cap != name),
+                None => {
+                    let name = entry.trim_start_matches('+').to_string();
+                    if !capabilities.wanted.contains(&name) {
+                        capabilities.wanted.push(name);
+                    }
+                }
+            }
+        }
+        capabilities
+    }
+
+    /// Records one line of a CAP LS reply. Returns whether the listing is complete.
+    pub fn ls(&mut self, caps: &str, more: bool) -> bool {
+        if !self.listing {
+            self.available.clear();
+        }
+        self.listing = more;
+        self.offer(caps);
+        !more
+    }
+
+    /// Records capabilities announced by CAP NEW and returns the ones to request.
+    pub fn new_caps(&mut self, caps: &str) -> Vec<String> {
+        self.offer(caps);
+        self.to_request()
+    }
+
+    fn offer(&mut self, caps: &str) {
+        for cap in caps.split_whitespace() {
+            let (name, value) = cap.split_once('=').unwrap_or((cap, ""));
+            self.available.insert(name.to_string(), value.to_string());
+        }
+    }
+
+    /// Forgets capabilities withdrawn by CAP DEL and returns the ones that were enabled.
+    pub fn del(&mut self, caps: &str) -> Vec<String> {
+        caps.split_whitespace()
+            .filter(|cap| {
+                self.available.remove(*cap);
+                self.pending.remove(*cap);
+                self.enabled.remove(*cap)
+            })
+            .map(|cap| cap.to_string())
+            .collect()
+    }
+
+    /// Wanted capabilities the server offers that are neither enabled nor requested yet.
+    /// `sasl` is left to the caller, which only asks for it with credentials to send.
+    pub fn to_request(&self) -> Vec<String> {
+        self.wanted.iter()
+            .filter(|cap| *cap != "sasl" && self.available.contains_key(*cap) && !self.enabled.contains(*cap) && !self.pending.contains(*cap))
+            .cloned()
+            .collect()
+    }
+
+    /// Marks capabilities as requested.
+    pub fn requested(&mut self, caps: &[String]) {
+        self.pending.extend(caps.iter().map(|cap| cap.trim_start_matches('-').to_string()));
+    }
+
+    /// Applies a CAP ACK, where `-name` disables a capability.
+    pub fn ack(&mut self, caps: &str) {
+        for cap in caps.split_whitespace() {
+            match cap.strip_prefix('-') {
+                Some(name) => {
+                    self.pending.remove(name);
+                    self.enabled.remove(name);
+                }
+                None => {
+                    self.pending.remove(cap);
+                    self.enabled.insert(cap.to_string());
+                }
+            }
+        }
+    }
+
+    /// Applies a CAP NAK, which rejects the whole request.
+    pub fn nak(&mut self, caps: &str) {
+        for cap in caps.split_whitespace() {
+            self.pending.remove(cap.trim_start_matches('-'));
+        }
+    }
+
+    /// Whether a capability is in effect.
+    pub fn is_enabled(&self, cap: &str) -> bool {
+        self.enabled.contains(cap)
+    }
+
+    /// Value the server advertised for a capability, such as the `sasl` mechanisms.
+    pub fn value(&self, cap: &str) -> Option<&str> {
+        self.available.get(cap).map(|value| value.as_str())
+    }
+
+    /// Forgets everything the server said, keeping the wanted set.
+    pub fn reset(&mut self) {
+        *self = Self {
+            wanted: std::mem::take(&mut self.wanted),
+            ..Default::default()
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiation() {
+        let mut caps = Capabilities::new(&["server-time", "echo-message", "batch", "sasl"]);
+        assert!(!caps.ls("multi-prefix server-time sasl=PLAIN,EXTERNAL", true));
+        assert!(caps.ls("batch", false));
+        assert_eq!(caps.value("sasl"), Some("PLAIN,EXTERNAL"));
+        let request = caps.to_request();
+        assert_eq!(request, vec!["server-time", "batch"]);
+        caps.requested(&request);
+        assert!(caps.to_request().is_empty());
+        caps.ack("server-time batch");
+        assert!(caps.is_enabled("batch"));
+        assert!(caps.pending.is_empty());
+
+        assert_eq!(caps.new_caps("echo-message away-notify"), vec!["echo-message"]);
+        caps.requested(&["echo-message".to_string()]);
+        caps.nak("echo-message");
+        assert!(!caps.is_enabled("echo-message"));
+        assert_eq!(caps.del("batch away-notify"), vec!["batch"]);
+        assert!(!caps.is_enabled("batch"));
+
+        caps.ack("-server-time");
+        assert!(caps.enabled.is_empty());
+    }
+
+    #[test]
+    fn relisting_replaces_offers() {
+        let mut caps = Capabilities::new(&[]);
+        caps.ls("batch chghost", false);
+        caps.ls("setname", false);
+        assert_eq!(caps.available.keys().collect::<Vec<_>>(), vec!["setname"]);
+    }
+
+    #[test]
+    fn option_overrides_defaults() {
+        assert_eq!(Capabilities::from_option(None).wanted.len(), DEFAULT_CAPABILITIES.len());
+        assert_eq!(Capabilities::from_option(Some("batch, chghost")).wanted, vec!["batch", "chghost"]);
+        let caps = Capabilities::from_option(Some("-echo-message +draft/chathistory"));
+        assert!(!caps.wanted.contains(&"echo-message".to_string()));
+        assert_eq!(caps.wanted.last().map(|cap| cap.as_str()), Some("draft/chathistory"));
+    }
+}
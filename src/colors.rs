@@ -0,0 +1,164 @@
+use std::collections::HashSet;
+use std::ops::Range;
+
+use irc::client::data::Config;
+use ratatui::style::Color;
+
+use crate::isupport::CaseMapping;
+
+/// Palette used unless the `nick_colors` option is set.
+pub const DEFAULT_PALETTE: [Color; 12] = [
+    Color::Cyan,
+    Color::Magenta,
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Red,
+    Color::LightCyan,
+    Color::LightMagenta,
+    Color::LightGreen,
+    Color::LightYellow,
+    Color::LightBlue,
+    Color::LightRed,
+];
+
+/// FNV-1a, chosen over the standard hasher so colors stay the same across
+/// runs and builds.
+fn hash(text: &str) -> u32 {
+    text.bytes().fold(0x811c9dc5, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x01000193))
+}
+
+/// Characters that may appear in a nickname.
+fn is_nick_char(c: char) -> bool {
+    c.is_alphanumeric() || "-[]\\`^{}|_".contains(c)
+}
+
+/// Byte ranges of the words of a text that could be nicknames.
+fn nick_words(text: &str) -> Vec<Range<usize>> {
+    let mut words = vec![];
+    let mut start = None;
+    for (index, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        match (is_nick_char(c), start) {
+            (true, None) => start = Some(index),
+            (false, Some(word)) => {
+                words.push(word..index);
+                start = None;
+            },
+            _ => {},
+        }
+    }
+    words
+}
+
+/// Whether a text mentions a nick as a whole word, compared under the case
+/// mapping the same way nicks are found for coloring.
+pub fn mentions(text: &str, nick: &str, casemapping: CaseMapping) -> bool {
+    !nick.is_empty() && nick_words(text).into_iter().any(|word| casemapping.eq(&text[word], nick))
+}
+
+/// Colors picked for nicknames.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NickColors {
+    pub palette: Vec<Color>,
+    /// Nicks given a color of their own, compared under the case mapping.
+    pub overrides: Vec<(String, Color)>,
+}
+
+impl Default for NickColors {
+    fn default() -> Self {
+        Self {
+            palette: DEFAULT_PALETTE.to_vec(),
+            overrides: vec![],
+        }
+    }
+}
+
+impl NickColors {
+    /// Reads the `nick_colors` and `nick_color_overrides` options, skipping
+    /// colors that do not parse.
+    pub fn from_config(config: &Config) -> Self {
+        let words = |option| config.get_option(option).into_iter().flat_map(|value| value.split([' ', ',']).filter(|word| !word.is_empty()));
+        let palette: Vec<Color> = words("nick_colors").filter_map(|color| color.parse().ok()).collect();
+        let overrides = words("nick_color_overrides")
+            .filter_map(|entry| entry.split_once(':'))
+            .filter_map(|(nick, color)| Some((nick.to_string(), color.parse().ok()?)))
+            .collect();
+        Self {
+            palette: match palette.is_empty() {
+                true => DEFAULT_PALETTE.to_vec(),
+                false => palette,
+            },
+            overrides,
+        }
+    }
+
+    /// Color of a nick: its override, or a palette entry picked by the hash
+    /// of the folded nick.
+    pub fn color(&self, nick: &str, casemapping: CaseMapping) -> Color {
+        if let Some((_, color)) = self.overrides.iter().find(|(overridden, _)| casemapping.eq(overridden, nick)) {
+            return *color;
+        }
+        match self.palette.len() {
+            0 => Color::Reset,
+            len => self.palette[hash(&casemapping.normalize(nick)) as usize % len],
+        }
+    }
+}
+
+/// Nick colors applied within one buffer, knowing which nicks are in it.
+pub struct NickPainter<'a> {
+    pub colors: &'a NickColors,
+    pub casemapping: CaseMapping,
+    /// Folded nicks colored when mentioned in message text.
+    pub nicks: HashSet<String>,
+}
+
+impl<'a> NickPainter<'a> {
+    pub fn new<'n>(colors: &'a NickColors, casemapping: CaseMapping, nicks: impl Iterator<Item = &'n str>) -> Self {
+        let nicks = nicks.map(|nick| casemapping.normalize(nick)).collect();
+        Self { colors, casemapping, nicks }
+    }
+
+    pub fn color(&self, nick: &str) -> Color {
+        self.colors.color(nick, self.casemapping)
+    }
+
+    /// Byte ranges of the known nicks mentioned in a text, as whole words,
+    /// with their colors.
+    pub fn mentions(&self, text: &str) -> Vec<(Range<usize>, Color)> {
+        nick_words(text).into_iter()
+            .filter(|word| self.nicks.contains(&self.casemapping.normalize(&text[word.clone()])))
+            .map(|word| (word.clone(), self.color(&text[word])))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stable_colors() {
+        let colors = NickColors::default();
+        let mapping = CaseMapping::Rfc1459;
+        assert_eq!(colors.color("Ferris[away]", mapping), colors.color("ferris{AWAY}", mapping));
+        assert_eq!(colors.color("ferris", mapping), DEFAULT_PALETTE[hash("ferris") as usize % DEFAULT_PALETTE.len()]);
+
+        let colors = NickColors { palette: vec![Color::Green], overrides: vec![("ChanServ".to_string(), Color::DarkGray)] };
+        assert_eq!(colors.color("chanserv", mapping), Color::DarkGray);
+        assert_eq!(colors.color("alice", mapping), Color::Green);
+    }
+
+    #[test]
+    fn mentions() {
+        let colors = NickColors { palette: vec![Color::Green], overrides: vec![] };
+        let painter = NickPainter::new(&colors, CaseMapping::Ascii, ["Alice", "bob"].into_iter());
+        let found: Vec<Range<usize>> = painter.mentions("alice: ask BOB, not bobby").into_iter().map(|(range, _)| range).collect();
+        assert_eq!(found, vec![0..5, 11..14]);
+
+        assert!(super::mentions("ping [Tim]: hi", "{tim}", CaseMapping::Rfc1459));
+        assert!(!super::mentions("ping [Tim]: hi", "{tim}", CaseMapping::Ascii));
+        assert!(!super::mentions("some timeout", "me", CaseMapping::Ascii));
+        assert!(!super::mentions("a timeout", "Tim", CaseMapping::Ascii));
+    }
+}
@@ -0,0 +1,566 @@
+use std::fmt;
+
+use crate::isupport::ServerInfo;
+
+/// How a command argument consumes the input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArgKind {
+    /// A single word that must be present.
+    Required,
+    /// A single word that may be omitted.
+    Optional,
+    /// The rest of the line, which must not be empty.
+    Text,
+    /// The rest of the line, which may be empty.
+    OptionalText,
+}
+
+/// What a single-word argument must look like.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Value {
+    Any,
+    Channel,
+    Nick,
+    /// A channel, a nickname or another name the server accepts, checked
+    /// when the command runs. The parser only rules out mode strings.
+    Target,
+}
+
+/// A named argument of a command.
+#[derive(Debug)]
+pub struct Arg {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    pub value: Value,
+}
+
+const fn required(name: &'static str, value: Value) -> Arg {
+    Arg { name, kind: ArgKind::Required, value }
+}
+
+const fn optional(name: &'static str, value: Value) -> Arg {
+    Arg { name, kind: ArgKind::Optional, value }
+}
+
+const fn text(name: &'static str) -> Arg {
+    Arg { name, kind: ArgKind::Text, value: Value::Any }
+}
+
+const fn optional_text(name: &'static str) -> Arg {
+    Arg { name, kind: ArgKind::OptionalText, value: Value::Any }
+}
+
+/// Reasons a command line can be rejected.
+#[derive(Debug, PartialEq, Eq)]
+pub enum CommandError {
+    Empty,
+    Unknown(String),
+    MissingArgument(&'static str),
+    TooManyArguments,
+    InvalidChannel(String),
+    InvalidNick(String),
+    InvalidTarget(String),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandError::Empty => write!(f, "No command given (see /help)"),
+            CommandError::Unknown(name) => write!(f, "Unknown command: /{} (see /help)", name),
+            CommandError::MissingArgument(name) => write!(f, "Missing argument <{}>", name),
+            CommandError::TooManyArguments => write!(f, "Too many arguments"),
+            CommandError::InvalidChannel(channel) => write!(f, "Not a channel name: {}", channel),
+            CommandError::InvalidNick(nick) => write!(f, "Not a valid nickname: {}", nick),
+            CommandError::InvalidTarget(target) => write!(f, "Not a channel or nickname: {}", target),
+        }
+    }
+}
+
+/// Checks a nickname against the RFC 2812 character rules.
+pub fn is_nick(name: &str) -> bool {
+    let special = "[]\\`_^{|}";
+    match name.chars().next() {
+        Some(first) if first.is_ascii_alphabetic() || special.contains(first) => {
+            name.chars().all(|c| c.is_ascii_alphanumeric() || special.contains(c) || c == '-')
+        }
+        _ => false,
+    }
+}
+
+/// Checks each comma-separated name of a message target. Besides channels
+/// and nicknames, bouncer services such as `*status` and names for which
+/// `known` holds, like buffers the app opened, are accepted.
+pub fn check_target(target: &str, server_info: &ServerInfo, known: impl Fn(&str) -> bool) -> Result<(), CommandError> {
+    let valid = |name: &str| server_info.is_channel(name) || is_nick(name) || name.strip_prefix('*').is_some_and(is_nick) || known(name);
+    match target.split(',').find(|name| !valid(name)) {
+        Some(name) => Err(CommandError::InvalidTarget(name.to_string())),
+        None => Ok(()),
+    }
+}
+
+/// Registry entry describing a command.
+#[derive(Debug)]
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub args: &'static [Arg],
+    pub description: &'static str,
+}
+
+/// Every command understood by the input line.
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "help",
+        aliases: &["h"],
+        args: &[optional("command", Value::Any)],
+        description: "List the commands, or show the usage of one command.",
+    },
+    CommandSpec {
+        name: "quit",
+        aliases: &["q"],
+        args: &[optional_text("reason")],
+        description: "Disconnect from the server and exit.",
+    },
+    CommandSpec {
+        name: "join",
+        aliases: &["j"],
+        args: &[required("channel", Value::Channel), optional("key", Value::Any)],
+        description: "Join a channel.",
+    },
+    CommandSpec {
+        name: "msg",
+        aliases: &["m", "query"],
+        args: &[required("target", Value::Target), text("message")],
+        description: "Send a private message and open a query buffer.",
+    },
+    CommandSpec {
+        name: "me",
+        aliases: &["action"],
+        args: &[text("action")],
+        description: "Send an action (CTCP ACTION) to the active buffer.",
+    },
+    CommandSpec {
+        name: "notice",
+        aliases: &["n"],
+        args: &[required("target", Value::Target), text("message")],
+        description: "Send a notice.",
+    },
+    CommandSpec {
+        name: "nick",
+        aliases: &[],
+        args: &[required("nickname", Value::Nick)],
+        description: "Change your nickname.",
+    },
+    CommandSpec {
+        name: "topic",
+        aliases: &["t"],
+        args: &[optional("channel", Value::Channel), optional_text("topic")],
+        description: "Show the topic of a channel, or set it when a topic is given.",
+    },
+    CommandSpec {
+        name: "part",
+        aliases: &["leave"],
+        args: &[optional("channel", Value::Channel), optional_text("reason")],
+        description: "Leave a channel, the active one by default.",
+    },
+    CommandSpec {
+        name: "quote",
+        aliases: &["raw"],
+        args: &[text("line")],
+        description: "Send a raw protocol line to the server.",
+    },
+    CommandSpec {
+        name: "mode",
+        aliases: &[],
+        args: &[optional("target", Value::Target), optional_text("modes")],
+        description: "Set or query modes of a channel (the active one by default) or of yourself.",
+    },
+    CommandSpec {
+        name: "op",
+        aliases: &[],
+        args: &[optional("channel", Value::Channel), text("nicks")],
+        description: "Give channel operator status.",
+    },
+    CommandSpec {
+        name: "deop",
+        aliases: &[],
+        args: &[optional("channel", Value::Channel), text("nicks")],
+        description: "Take channel operator status.",
+    },
+    CommandSpec {
+        name: "voice",
+        aliases: &[],
+        args: &[optional("channel", Value::Channel), text("nicks")],
+        description: "Give voice.",
+    },
+    CommandSpec {
+        name: "devoice",
+        aliases: &[],
+        args: &[optional("channel", Value::Channel), text("nicks")],
+        description: "Take voice.",
+    },
+    CommandSpec {
+        name: "kick",
+        aliases: &["k"],
+        args: &[optional("channel", Value::Channel), required("nick", Value::Nick), optional_text("reason")],
+        description: "Kick a user from a channel.",
+    },
+    CommandSpec {
+        name: "ban",
+        aliases: &["b"],
+        args: &[optional("channel", Value::Channel), text("nicks or masks")],
+        description: "Ban masks, deriving them from the nick's user@host when a nick is given.",
+    },
+    CommandSpec {
+        name: "unban",
+        aliases: &[],
+        args: &[optional("channel", Value::Channel), text("nicks or masks")],
+        description: "Remove bans.",
+    },
+    CommandSpec {
+        name: "kickban",
+        aliases: &["kb"],
+        args: &[optional("channel", Value::Channel), required("nick", Value::Nick), optional_text("reason")],
+        description: "Ban a user's mask, then kick them.",
+    },
+    CommandSpec {
+        name: "invite",
+        aliases: &[],
+        args: &[required("nick", Value::Nick), optional("channel", Value::Channel)],
+        description: "Invite a user to a channel, the active one by default.",
+    },
+    CommandSpec {
+        name: "close",
+        aliases: &["c"],
+        args: &[],
+        description: "Close the active buffer, leaving the channel if needed.",
+    },
+    CommandSpec {
+        name: "cap",
+        aliases: &[],
+        args: &[optional("subcommand", Value::Any), optional_text("capabilities")],
+        description: "Show enabled capabilities, `/cap ls` lists the offered ones and `/cap req <caps>` requests more.",
+    },
+    CommandSpec {
+        name: "search",
+        aliases: &["grep"],
+        args: &[text("query")],
+        description: "Search the active buffer, every open one with -all or the logs with -logs. Filter with -regex, -nick <nick>, -from and -to <YYYY-MM-DD>. Enter opens the selected result.",
+    },
+    CommandSpec {
+        name: "users",
+        aliases: &["u"],
+        args: &[],
+        description: "Toggle the user list.",
+    },
+];
+
+impl CommandSpec {
+    /// Builds the usage line, e.g. `/join <channel> [key]`.
+    pub fn usage(&self) -> String {
+        let mut usage = format!("/{}", self.name);
+        for arg in self.args {
+            match arg.kind {
+                ArgKind::Required => usage.push_str(&format!(" <{}>", arg.name)),
+                ArgKind::Optional => usage.push_str(&format!(" [{}]", arg.name)),
+                ArgKind::Text => usage.push_str(&format!(" <{}...>", arg.name)),
+                ArgKind::OptionalText => usage.push_str(&format!(" [{}...]", arg.name)),
+            }
+        }
+        usage
+    }
+
+    /// Help text shown by `/help <command>`.
+    pub fn help(&self) -> Vec<String> {
+        let mut help = vec![format!("{} - {}", self.usage(), self.description)];
+        if !self.aliases.is_empty() {
+            let aliases: Vec<String> = self.aliases.iter().map(|alias| format!("/{}", alias)).collect();
+            help.push(format!("Aliases: {}", aliases.join(", ")));
+        }
+        help
+    }
+
+    /// Splits and validates the text following the command name.
+    ///
+    /// Omitted optional arguments are simply absent from the result. An optional
+    /// word that does not match its expected value is treated as omitted, so
+    /// `/topic new topic` and `/topic #chan new topic` both parse.
+    pub fn parse_args(&self, rest: &str, server_info: &ServerInfo) -> Result<Vec<String>, CommandError> {
+        let mut args = vec![];
+        let mut rest = rest.trim();
+        for (index, arg) in self.args.iter().enumerate() {
+            if rest.is_empty() {
+                let missing = self.args[index..].iter().find(|arg| matches!(arg.kind, ArgKind::Required | ArgKind::Text));
+                match missing {
+                    Some(arg) => return Err(CommandError::MissingArgument(arg.name)),
+                    None => break,
+                }
+            }
+            match arg.kind {
+                ArgKind::Required | ArgKind::Optional => {
+                    let (word, remainder) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                    match validate(word, arg.value, server_info) {
+                        Ok(()) => {}
+                        Err(_) if arg.kind == ArgKind::Optional => continue,
+                        Err(err) => return Err(err),
+                    }
+                    args.push(word.to_string());
+                    rest = remainder.trim_start();
+                }
+                ArgKind::Text | ArgKind::OptionalText => {
+                    args.push(rest.to_string());
+                    rest = "";
+                }
+            }
+        }
+        if !rest.is_empty() {
+            return Err(CommandError::TooManyArguments);
+        }
+        Ok(args)
+    }
+}
+
+fn validate(word: &str, value: Value, server_info: &ServerInfo) -> Result<(), CommandError> {
+    match value {
+        Value::Any => Ok(()),
+        Value::Channel if server_info.is_channel(word) => Ok(()),
+        Value::Channel => Err(CommandError::InvalidChannel(word.to_string())),
+        Value::Nick if is_nick(word) => Ok(()),
+        Value::Nick => Err(CommandError::InvalidNick(word.to_string())),
+        Value::Target if !word.starts_with(['+', '-']) => Ok(()),
+        Value::Target => Err(CommandError::InvalidTarget(word.to_string())),
+    }
+}
+
+/// Resolves a command line (without the leading `/`) to its spec and validated arguments.
+pub fn parse(line: &str, server_info: &ServerInfo) -> Result<(&'static CommandSpec, Vec<String>), CommandError> {
+    let line = line.trim_start();
+    let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    if name.is_empty() {
+        return Err(CommandError::Empty);
+    }
+    let spec = find(name).ok_or_else(|| CommandError::Unknown(name.to_string()))?;
+    let args = spec.parse_args(rest, server_info)?;
+    Ok((spec, args))
+}
+
+/// Looks up a command by name or alias, ignoring case.
+pub fn find(name: &str) -> Option<&'static CommandSpec> {
+    let name = name.to_ascii_lowercase();
+    COMMANDS.iter().find(|spec| spec.name == name || spec.aliases.contains(&name.as_str()))
+}
+
+/// A line typed into the input box.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Input<'a> {
+    /// Text to send to the active buffer.
+    Text(&'a str),
+    /// A command line without its leading `/`.
+    Command(&'a str),
+}
+
+/// Classifies an input line: `/name args` is a command and `//text` sends `/text` literally.
+pub fn parse_input(input: &str) -> Input<'_> {
+    match input.strip_prefix('/') {
+        Some(literal) if literal.starts_with('/') => Input::Text(literal),
+        Some(command) => Input::Command(command),
+        None => Input::Text(input),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isupport::DEFAULT_CHANTYPES;
+
+    fn run(line: &str) -> Result<Vec<String>, CommandError> {
+        parse(line, &ServerInfo::default()).map(|(_, args)| args)
+    }
+
+    #[test]
+    fn input_classification() {
+        assert_eq!(parse_input("hello"), Input::Text("hello"));
+        assert_eq!(parse_input("//shrug"), Input::Text("/shrug"));
+        assert_eq!(parse_input("/join #rust"), Input::Command("join #rust"));
+        assert_eq!(parse_input("/"), Input::Command(""));
+    }
+
+    #[test]
+    fn empty_and_unknown() {
+        assert_eq!(run(""), Err(CommandError::Empty));
+        assert_eq!(run("   "), Err(CommandError::Empty));
+        assert_eq!(run("frobnicate"), Err(CommandError::Unknown("frobnicate".to_string())));
+    }
+
+    #[test]
+    fn aliases_resolve() {
+        for (alias, name) in [("h", "help"), ("q", "quit"), ("j", "join"), ("m", "msg"), ("query", "msg"), ("c", "close"), ("u", "users"), ("JOIN", "join")] {
+            assert_eq!(find(alias).map(|spec| spec.name), Some(name));
+        }
+    }
+
+    #[test]
+    fn help() {
+        assert_eq!(run("help"), Ok(vec![]));
+        assert_eq!(run("help join"), Ok(vec!["join".to_string()]));
+        assert_eq!(run("help join msg"), Err(CommandError::TooManyArguments));
+    }
+
+    #[test]
+    fn quit() {
+        assert_eq!(run("quit"), Ok(vec![]));
+        assert_eq!(run("q see you  later"), Ok(vec!["see you  later".to_string()]));
+    }
+
+    #[test]
+    fn join() {
+        assert_eq!(run("join"), Err(CommandError::MissingArgument("channel")));
+        assert_eq!(run("j   "), Err(CommandError::MissingArgument("channel")));
+        assert_eq!(run("join #rust"), Ok(vec!["#rust".to_string()]));
+        assert_eq!(run("join &local secret"), Ok(vec!["&local".to_string(), "secret".to_string()]));
+        for prefix in DEFAULT_CHANTYPES.chars() {
+            assert_eq!(run(&format!("join {}rust", prefix)), Ok(vec![format!("{}rust", prefix)]));
+        }
+        assert_eq!(run("join rust"), Err(CommandError::InvalidChannel("rust".to_string())));
+        assert_eq!(run("join #"), Err(CommandError::InvalidChannel("#".to_string())));
+        assert_eq!(run("join #a key extra"), Err(CommandError::TooManyArguments));
+    }
+
+    #[test]
+    fn server_chantypes() {
+        let info = ServerInfo { chantypes: "!".to_string(), ..Default::default() };
+        assert_eq!(parse("join !ops", &info).map(|(_, args)| args), Ok(vec!["!ops".to_string()]));
+        assert_eq!(parse("join #rust", &info).map(|(_, args)| args), Err(CommandError::InvalidChannel("#rust".to_string())));
+        assert_eq!(parse("topic #rust hi", &info).map(|(_, args)| args), Ok(vec!["#rust hi".to_string()]));
+    }
+
+    #[test]
+    fn msg() {
+        assert_eq!(run("msg"), Err(CommandError::MissingArgument("target")));
+        assert_eq!(run("msg alice"), Err(CommandError::MissingArgument("message")));
+        assert_eq!(run("msg alice hi there"), Ok(vec!["alice".to_string(), "hi there".to_string()]));
+        assert_eq!(run("m #rust hi"), Ok(vec!["#rust".to_string(), "hi".to_string()]));
+        assert_eq!(run("msg *status help"), Ok(vec!["*status".to_string(), "help".to_string()]));
+        assert_eq!(run("msg -v hi"), Err(CommandError::InvalidTarget("-v".to_string())));
+    }
+
+    #[test]
+    fn me() {
+        assert_eq!(run("me"), Err(CommandError::MissingArgument("action")));
+        assert_eq!(run("me waves hello"), Ok(vec!["waves hello".to_string()]));
+    }
+
+    #[test]
+    fn notice() {
+        assert_eq!(run("notice"), Err(CommandError::MissingArgument("target")));
+        assert_eq!(run("notice bob"), Err(CommandError::MissingArgument("message")));
+        assert_eq!(run("notice bob heads up"), Ok(vec!["bob".to_string(), "heads up".to_string()]));
+        assert_eq!(run("notice b@d hi"), Ok(vec!["b@d".to_string(), "hi".to_string()]));
+    }
+
+    #[test]
+    fn targets() {
+        let unknown = |_: &str| false;
+        let info = ServerInfo::default();
+        assert_eq!(check_target("alice,#rust", &info, unknown), Ok(()));
+        assert_eq!(check_target("*status", &info, unknown), Ok(()));
+        assert_eq!(check_target("alice,4lice", &info, unknown), Err(CommandError::InvalidTarget("4lice".to_string())));
+        assert_eq!(check_target("b@d", &info, unknown), Err(CommandError::InvalidTarget("b@d".to_string())));
+        assert_eq!(check_target("b@d", &info, |name| name == "b@d"), Ok(()));
+    }
+
+    #[test]
+    fn search() {
+        assert_eq!(run("search"), Err(CommandError::MissingArgument("query")));
+        assert_eq!(run("search -all -nick bob  spaced #words"), Ok(vec!["-all -nick bob  spaced #words".to_string()]));
+    }
+
+    #[test]
+    fn nick() {
+        assert_eq!(run("nick"), Err(CommandError::MissingArgument("nickname")));
+        assert_eq!(run("nick alice_"), Ok(vec!["alice_".to_string()]));
+        assert_eq!(run("nick 9lives"), Err(CommandError::InvalidNick("9lives".to_string())));
+        assert_eq!(run("nick alice bob"), Err(CommandError::TooManyArguments));
+    }
+
+    #[test]
+    fn topic() {
+        assert_eq!(run("topic"), Ok(vec![]));
+        assert_eq!(run("topic #rust"), Ok(vec!["#rust".to_string()]));
+        assert_eq!(run("topic Welcome all"), Ok(vec!["Welcome all".to_string()]));
+        assert_eq!(run("topic #rust Welcome all"), Ok(vec!["#rust".to_string(), "Welcome all".to_string()]));
+    }
+
+    #[test]
+    fn part() {
+        assert_eq!(run("part"), Ok(vec![]));
+        assert_eq!(run("part #rust"), Ok(vec!["#rust".to_string()]));
+        assert_eq!(run("part bye now"), Ok(vec!["bye now".to_string()]));
+        assert_eq!(run("part #rust bye"), Ok(vec!["#rust".to_string(), "bye".to_string()]));
+    }
+
+    #[test]
+    fn quote() {
+        assert_eq!(run("quote"), Err(CommandError::MissingArgument("line")));
+        assert_eq!(run("raw WHOIS alice"), Ok(vec!["WHOIS alice".to_string()]));
+    }
+
+    #[test]
+    fn operator_commands() {
+        for name in ["op", "deop", "voice", "devoice", "ban", "unban"] {
+            assert!(run(name).is_err(), "{}", name);
+            assert_eq!(run(&format!("{} alice bob", name)), Ok(vec!["alice bob".to_string()]));
+            assert_eq!(run(&format!("{} #rust alice", name)), Ok(vec!["#rust".to_string(), "alice".to_string()]));
+        }
+        assert_eq!(run("mode"), Ok(vec![]));
+        assert_eq!(run("mode #rust"), Ok(vec!["#rust".to_string()]));
+        assert_eq!(run("mode +m"), Ok(vec!["+m".to_string()]));
+        assert_eq!(run("mode #rust +l 10"), Ok(vec!["#rust".to_string(), "+l 10".to_string()]));
+    }
+
+    #[test]
+    fn kick_and_invite() {
+        for name in ["kick", "kickban"] {
+            assert_eq!(run(name), Err(CommandError::MissingArgument("nick")));
+            assert_eq!(run(&format!("{} #rust", name)), Err(CommandError::MissingArgument("nick")));
+            assert_eq!(run(&format!("{} bob spam", name)), Ok(vec!["bob".to_string(), "spam".to_string()]));
+            assert_eq!(run(&format!("{} #rust bob", name)), Ok(vec!["#rust".to_string(), "bob".to_string()]));
+            assert_eq!(run(&format!("{} b!b", name)), Err(CommandError::InvalidNick("b!b".to_string())));
+        }
+        assert_eq!(run("invite"), Err(CommandError::MissingArgument("nick")));
+        assert_eq!(run("invite bob"), Ok(vec!["bob".to_string()]));
+        assert_eq!(run("invite bob #rust"), Ok(vec!["bob".to_string(), "#rust".to_string()]));
+        assert_eq!(run("invite bob rust"), Err(CommandError::TooManyArguments));
+    }
+
+    #[test]
+    fn close_and_users() {
+        assert_eq!(run("close"), Ok(vec![]));
+        assert_eq!(run("close now"), Err(CommandError::TooManyArguments));
+        assert_eq!(run("users"), Ok(vec![]));
+        assert_eq!(run("u please"), Err(CommandError::TooManyArguments));
+    }
+
+    #[test]
+    fn cap() {
+        assert_eq!(run("cap"), Ok(vec![]));
+        assert_eq!(run("cap ls"), Ok(vec!["ls".to_string()]));
+        assert_eq!(run("cap req batch -echo-message"), Ok(vec!["req".to_string(), "batch -echo-message".to_string()]));
+    }
+
+    #[test]
+    fn usage_lines() {
+        assert_eq!(find("join").unwrap().usage(), "/join <channel> [key]");
+        assert_eq!(find("msg").unwrap().usage(), "/msg <target> <message...>");
+        assert_eq!(find("quit").unwrap().usage(), "/quit [reason...]");
+    }
+
+    #[test]
+    fn nick_rules() {
+        assert!(is_nick("alice"));
+        assert!(is_nick("[bot]_-"));
+        assert!(!is_nick(""));
+        assert!(!is_nick("-alice"));
+        assert!(!is_nick("ali#ce"));
+    }
+}
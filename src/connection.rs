@@ -0,0 +1,171 @@
+use std::time::{Duration, Instant};
+
+/// Where the connection to the server stands.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Opening the connection.
+    #[default]
+    Connecting,
+    /// Connected, negotiating capabilities and registering.
+    Registering,
+    /// Registered and usable.
+    Connected,
+    /// Connection lost, the next attempt is due at `retry_at`.
+    Disconnected { retry_at: Instant },
+}
+
+impl ConnectionState {
+    /// Short label for the interface.
+    pub fn label(&self) -> &'static str {
+        match self {
+            ConnectionState::Connecting => "connecting",
+            ConnectionState::Registering => "registering",
+            ConnectionState::Connected => "connected",
+            ConnectionState::Disconnected { .. } => "disconnected",
+        }
+    }
+}
+
+/// Exponential backoff between reconnection attempts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Backoff {
+    pub base: Duration,
+    pub max: Duration,
+    /// Failed attempts since the last successful registration.
+    pub attempt: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(2), Duration::from_secs(300))
+    }
+}
+
+impl Backoff {
+    /// Constructs a new instance of [`Backoff`].
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self { base, max, attempt: 0 }
+    }
+
+    /// Delay before the next attempt, doubling with every failure up to the maximum.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.base.saturating_mul(2u32.saturating_pow(self.attempt)).min(self.max);
+        self.attempt = self.attempt.saturating_add(1);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+/// Round-trip lag measured with our own PINGs, and detection of a silent server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lag {
+    /// Time between PINGs.
+    pub interval: Duration,
+    /// Silence after which the connection is considered dead.
+    pub timeout: Duration,
+    /// Round-trip time of the last answered PING.
+    pub last: Option<Duration>,
+    /// Token and send time of the PING awaiting its PONG.
+    pending: Option<(String, Instant)>,
+    last_ping: Instant,
+    last_activity: Instant,
+    sent: u64,
+}
+
+impl Default for Lag {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(30), Duration::from_secs(120))
+    }
+}
+
+impl Lag {
+    /// Constructs a new instance of [`Lag`].
+    pub fn new(interval: Duration, timeout: Duration) -> Self {
+        let now = Instant::now();
+        Self { interval, timeout, last: None, pending: None, last_ping: now, last_activity: now, sent: 0 }
+    }
+
+    /// Starts over for a new connection.
+    pub fn reset(&mut self, now: Instant) {
+        *self = Self { last_ping: now, last_activity: now, sent: self.sent, ..Self::new(self.interval, self.timeout) };
+    }
+
+    /// Records that data arrived from the server.
+    pub fn activity(&mut self, now: Instant) {
+        self.last_activity = now;
+    }
+
+    /// Token for the next PING when one is due.
+    pub fn ping_due(&mut self, now: Instant) -> Option<String> {
+        if self.pending.is_some() || now.duration_since(self.last_ping) < self.interval {
+            return None;
+        }
+        self.sent += 1;
+        let token = format!("ironirc-{}", self.sent);
+        self.pending = Some((token.clone(), now));
+        self.last_ping = now;
+        Some(token)
+    }
+
+    /// Matches a PONG against the outstanding PING. Returns whether it answered it.
+    pub fn pong(&mut self, token: &str, now: Instant) -> bool {
+        match &self.pending {
+            Some((pending, sent)) if pending == token => {
+                self.last = Some(now.duration_since(*sent));
+                self.pending = None;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Lag to show: the last round trip, or the wait for the current PING once it is longer.
+    pub fn current(&self, now: Instant) -> Option<Duration> {
+        let waiting = self.pending.as_ref().map(|(_, sent)| now.duration_since(*sent));
+        match (self.last, waiting) {
+            (Some(last), Some(waiting)) => Some(last.max(waiting)),
+            (last, waiting) => last.or(waiting.filter(|waiting| *waiting >= self.interval)),
+        }
+    }
+
+    /// Whether nothing has arrived from the server for longer than the timeout.
+    pub fn timed_out(&self, now: Instant) -> bool {
+        now.duration_since(self.last_activity) >= self.timeout
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let mut backoff = Backoff::new(Duration::from_secs(2), Duration::from_secs(10));
+        let delays: Vec<u64> = (0..5).map(|_| backoff.next_delay().as_secs()).collect();
+        assert_eq!(delays, vec![2, 4, 8, 10, 10]);
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_secs(2));
+        backoff.attempt = u32::MAX;
+        assert_eq!(backoff.next_delay(), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn lag_and_timeout() {
+        let start = Instant::now();
+        let mut lag = Lag::new(Duration::from_secs(30), Duration::from_secs(120));
+        lag.reset(start);
+        assert_eq!(lag.ping_due(start + Duration::from_secs(10)), None);
+        let token = lag.ping_due(start + Duration::from_secs(30)).unwrap();
+        assert_eq!(lag.ping_due(start + Duration::from_secs(61)), None);
+        assert!(!lag.pong("other", start + Duration::from_secs(31)));
+        assert!(lag.pong(&token, start + Duration::from_millis(30_250)));
+        assert_eq!(lag.current(start + Duration::from_secs(40)), Some(Duration::from_millis(250)));
+
+        lag.activity(start + Duration::from_secs(60));
+        assert!(!lag.timed_out(start + Duration::from_secs(179)));
+        assert!(lag.timed_out(start + Duration::from_secs(180)));
+    }
+}
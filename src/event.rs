@@ -2,12 +2,15 @@ use std::time::Duration;
 
 use crossterm::event::{Event as CrosstermEvent, KeyEvent, MouseEvent};
 use futures::{FutureExt, StreamExt};
+use irc::client::data::Config;
+use irc::client::Client;
+use irc::proto::Message;
 use tokio::sync::mpsc;
 
 use crate::app::AppResult;
 
 /// Terminal events.
-#[derive(Clone, Copy, Debug)]
+#[derive(Debug)]
 pub enum Event {
     /// Terminal tick.
     Tick,
@@ -17,6 +20,15 @@ pub enum Event {
     Mouse(MouseEvent),
     /// Terminal resize.
     Resize(u16, u16),
+    /// Message received from the IRC server.
+    Irc(Message),
+    /// Connection to the IRC server established.
+    Connected(Box<Client>),
+    /// Connection attempt failed or the connection was lost.
+    Disconnected(String),
+    /// The server refused a nickname with 433 or 432, which the irc crate
+    /// reports as an error instead of the message.
+    NickUnavailable,
 }
 
 /// Terminal event handler.
@@ -29,6 +41,8 @@ pub struct EventHandler {
     receiver: mpsc::UnboundedReceiver<Event>,
     /// Event handler thread.
     handler: tokio::task::JoinHandle<()>,
+    /// IRC connection and stream reader thread.
+    irc_handler: Option<tokio::task::JoinHandle<()>>,
 }
 
 impl EventHandler {
@@ -75,6 +89,61 @@ impl EventHandler {
             sender,
             receiver,
             handler,
+            irc_handler: None,
+        }
+    }
+
+    /// Connects to the server in the background. The new client is handed
+    /// over as [`Event::Connected`], its messages follow as [`Event::Irc`]
+    /// and the end of the connection is reported as [`Event::Disconnected`].
+    ///
+    /// Any previous connection is dropped.
+    pub fn connect(&mut self, config: Config) {
+        self.disconnect();
+        let _sender = self.sender.clone();
+        self.irc_handler = Some(tokio::spawn(async move {
+            let mut client = match Client::from_config(config).await {
+                Ok(client) => client,
+                Err(err) => {
+                    let _ = _sender.send(Event::Disconnected(err.to_string()));
+                    return;
+                }
+            };
+            let mut stream = match client.stream() {
+                Ok(stream) => stream,
+                Err(err) => {
+                    let _ = _sender.send(Event::Disconnected(err.to_string()));
+                    return;
+                }
+            };
+            if _sender.send(Event::Connected(Box::new(client))).is_err() {
+                return;
+            }
+            let reason = loop {
+                match stream.next().await {
+                    Some(Ok(message)) => {
+                        if _sender.send(Event::Irc(message)).is_err() {
+                            return;
+                        }
+                    }
+                    Some(Err(irc::error::Error::NoUsableNick)) => {
+                        if _sender.send(Event::NickUnavailable).is_err() {
+                            return;
+                        }
+                    }
+                    Some(Err(err)) => break err.to_string(),
+                    None => break "Connection closed".to_string(),
+                }
+            };
+            let _ = _sender.send(Event::Disconnected(reason));
+        }));
+    }
+
+    /// Stops the connection task, so a connection given up on, such as after
+    /// a ping timeout, sends no more events.
+    pub fn disconnect(&mut self) {
+        if let Some(irc_handler) = self.irc_handler.take() {
+            irc_handler.abort();
         }
     }
 
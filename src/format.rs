@@ -0,0 +1,270 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Span;
+
+pub const BOLD: char = '\x02';
+pub const COLOR: char = '\x03';
+pub const HEX_COLOR: char = '\x04';
+pub const RESET: char = '\x0F';
+pub const MONOSPACE: char = '\x11';
+pub const REVERSE: char = '\x16';
+pub const ITALIC: char = '\x1D';
+pub const STRIKETHROUGH: char = '\x1E';
+pub const UNDERLINE: char = '\x1F';
+
+/// Colors 16 to 98 of the extended palette.
+const EXTENDED_COLORS: [u32; 83] = [
+    0x470000, 0x472100, 0x474700, 0x324700, 0x004700, 0x00472c, 0x004747, 0x002747, 0x000047, 0x2e0047, 0x470047, 0x47002a,
+    0x740000, 0x743a00, 0x747400, 0x517400, 0x007400, 0x007449, 0x007474, 0x004074, 0x000074, 0x4b0074, 0x740074, 0x740045,
+    0xb50000, 0xb56300, 0xb5b500, 0x7db500, 0x00b500, 0x00b571, 0x00b5b5, 0x0063b5, 0x0000b5, 0x7500b5, 0xb500b5, 0xb5006b,
+    0xff0000, 0xff8c00, 0xffff00, 0xb2ff00, 0x00ff00, 0x00ffa0, 0x00ffff, 0x008cff, 0x0000ff, 0xa500ff, 0xff00ff, 0xff0098,
+    0xff5959, 0xffb459, 0xffff71, 0xcfff60, 0x6fff6f, 0x65ffc9, 0x6dffff, 0x59b4ff, 0x5959ff, 0xc459ff, 0xff66ff, 0xff59bc,
+    0xff9c9c, 0xffd39c, 0xffff9c, 0xe2ff9c, 0x9cff9c, 0x9cffdb, 0x9cffff, 0x9cd3ff, 0x9c9cff, 0xdc9cff, 0xff9cff, 0xff94d3,
+    0x000000, 0x131313, 0x282828, 0x363636, 0x4d4d4d, 0x656565, 0x818181, 0x9f9f9f, 0xbcbcbc, 0xe2e2e2, 0xffffff,
+];
+
+/// Terminal color of a mIRC color number. The first 16 follow the terminal
+/// theme, 99 and anything unknown mean the default color.
+pub fn color(code: u8) -> Option<Color> {
+    let color = match code {
+        0 => Color::White,
+        1 => Color::Black,
+        2 => Color::Blue,
+        3 => Color::Green,
+        4 => Color::LightRed,
+        5 => Color::Red,
+        6 => Color::Magenta,
+        7 => Color::Yellow,
+        8 => Color::LightYellow,
+        9 => Color::LightGreen,
+        10 => Color::Cyan,
+        11 => Color::LightCyan,
+        12 => Color::LightBlue,
+        13 => Color::LightMagenta,
+        14 => Color::DarkGray,
+        15 => Color::Gray,
+        16..=98 => {
+            let rgb = EXTENDED_COLORS[code as usize - 16];
+            Color::Rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+        },
+        _ => return None,
+    };
+    Some(color)
+}
+
+/// Formatting in effect at a point of the text.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Format {
+    bold: bool,
+    italic: bool,
+    underline: bool,
+    strikethrough: bool,
+    reverse: bool,
+    foreground: Option<Color>,
+    background: Option<Color>,
+}
+
+impl Format {
+    fn style(&self, base: Style) -> Style {
+        let mut style = base;
+        if let Some(foreground) = self.foreground {
+            style = style.fg(foreground);
+        }
+        if let Some(background) = self.background {
+            style = style.bg(background);
+        }
+        for (on, modifier) in [
+            (self.bold, Modifier::BOLD),
+            (self.italic, Modifier::ITALIC),
+            (self.underline, Modifier::UNDERLINED),
+            (self.strikethrough, Modifier::CROSSED_OUT),
+            (self.reverse, Modifier::REVERSED),
+        ] {
+            if on {
+                style = style.add_modifier(modifier);
+            }
+        }
+        style
+    }
+}
+
+/// Takes up to `max` characters accepted by `accept`, or exactly `max` when
+/// `exact` is set, taking nothing if fewer follow.
+fn take(chars: &mut Peekable<Chars>, max: usize, exact: bool, accept: fn(&char) -> bool) -> String {
+    let mut ahead = chars.clone();
+    let mut taken = String::new();
+    while taken.len() < max {
+        match ahead.next_if(accept) {
+            Some(c) => taken.push(c),
+            None => break,
+        }
+    }
+    if exact && taken.len() < max {
+        return String::new();
+    }
+    *chars = ahead;
+    taken
+}
+
+/// Reads the `FG[,BG]` after a color code, where each part is up to `len`
+/// characters, or exactly `len` for hex colors. A comma only belongs to the
+/// code when a color follows it.
+fn color_args(chars: &mut Peekable<Chars>, len: usize, exact: bool, accept: fn(&char) -> bool) -> Option<(String, Option<String>)> {
+    let foreground = take(chars, len, exact, accept);
+    if foreground.is_empty() {
+        return None;
+    }
+    let mut ahead = chars.clone();
+    let background = match ahead.next() {
+        Some(',') => Some(take(&mut ahead, len, exact, accept)).filter(|background| !background.is_empty()),
+        _ => None,
+    };
+    if background.is_some() {
+        *chars = ahead;
+    }
+    Some((foreground, background))
+}
+
+/// The arguments of a color code as written.
+fn args(foreground: &str, background: &Option<String>) -> String {
+    match background {
+        Some(background) => format!("{},{}", foreground, background),
+        None => foreground.to_string(),
+    }
+}
+
+fn hex(color: &str) -> Option<Color> {
+    let rgb = u32::from_str_radix(color, 16).ok().filter(|_| color.len() == 6)?;
+    Some(Color::Rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+}
+
+/// Code inserted by Ctrl and a key in the input line.
+pub fn for_key(key: char) -> Option<char> {
+    match key {
+        'b' => Some(BOLD),
+        'k' => Some(COLOR),
+        'i' => Some(ITALIC),
+        'u' => Some(UNDERLINE),
+        'r' => Some(REVERSE),
+        'o' => Some(RESET),
+        _ => None,
+    }
+}
+
+/// Converts text with mIRC formatting codes into styled spans on top of
+/// `base`. Monospace has nothing to change in a terminal and is dropped.
+pub fn parse(text: &str, base: Style) -> Vec<Span<'static>> {
+    spans(text, base, false)
+}
+
+/// Like [`parse`], but keeps each code visible as a reversed marker in caret
+/// notation, such as `B` for bold or `C4,12` for a color, for the input line.
+pub fn markers(text: &str, base: Style) -> Vec<Span<'static>> {
+    spans(text, base, true)
+}
+
+fn spans(text: &str, base: Style, markers: bool) -> Vec<Span<'static>> {
+    let mut spans = vec![];
+    let mut format = Format::default();
+    let mut current = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if ![BOLD, COLOR, HEX_COLOR, RESET, MONOSPACE, REVERSE, ITALIC, STRIKETHROUGH, UNDERLINE].contains(&c) {
+            current.push(c);
+            continue;
+        }
+        if !current.is_empty() {
+            spans.push(Span::styled(std::mem::take(&mut current), format.style(base)));
+        }
+        let mut marker = (((c as u8) + 0x40) as char).to_string();
+        match c {
+            BOLD => format.bold = !format.bold,
+            ITALIC => format.italic = !format.italic,
+            UNDERLINE => format.underline = !format.underline,
+            STRIKETHROUGH => format.strikethrough = !format.strikethrough,
+            REVERSE => format.reverse = !format.reverse,
+            RESET => format = Format::default(),
+            COLOR => match color_args(&mut chars, 2, false, char::is_ascii_digit) {
+                Some((foreground, background)) => {
+                    marker.push_str(&args(&foreground, &background));
+                    format.foreground = foreground.parse().ok().and_then(color);
+                    if let Some(background) = background {
+                        format.background = background.parse().ok().and_then(color);
+                    }
+                },
+                None => (format.foreground, format.background) = (None, None),
+            },
+            HEX_COLOR => match color_args(&mut chars, 6, true, char::is_ascii_hexdigit) {
+                Some((foreground, background)) => {
+                    marker.push_str(&args(&foreground, &background));
+                    format.foreground = hex(&foreground);
+                    if let Some(background) = background {
+                        format.background = hex(&background);
+                    }
+                },
+                None => (format.foreground, format.background) = (None, None),
+            },
+            _ => {},
+        }
+        if markers {
+            spans.push(Span::styled(marker, base.add_modifier(Modifier::REVERSED)));
+        }
+    }
+    if !current.is_empty() {
+        spans.push(Span::styled(current, format.style(base)));
+    }
+    spans
+}
+
+/// Removes every formatting code from a text.
+pub fn strip(text: &str) -> String {
+    parse(text, Style::default()).into_iter().map(|span| span.content.into_owned()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn styled(text: &str) -> Vec<(String, Style)> {
+        parse(text, Style::default()).into_iter().map(|span| (span.content.into_owned(), span.style)).collect()
+    }
+
+    #[test]
+    fn codes() {
+        assert_eq!(styled("plain"), vec![("plain".to_string(), Style::default())]);
+        assert_eq!(styled("\x02bold\x02 \x1Dit\x0F."), vec![
+            ("bold".to_string(), Style::default().add_modifier(Modifier::BOLD)),
+            (" ".to_string(), Style::default()),
+            ("it".to_string(), Style::default().add_modifier(Modifier::ITALIC)),
+            (".".to_string(), Style::default()),
+        ]);
+        assert_eq!(styled("\x034,12red\x03 done"), vec![
+            ("red".to_string(), Style::default().fg(Color::LightRed).bg(Color::LightBlue)),
+            (" done".to_string(), Style::default()),
+        ]);
+        // A comma not followed by a color is text, and only two digits are read.
+        assert_eq!(strip("\x034,x \x03123"), ",x 3");
+        assert_eq!(styled("\x0352x")[0].1, Style::default().fg(Color::Rgb(0xff, 0x00, 0x00)));
+        assert_eq!(styled("\x0399x")[0].1, Style::default());
+        assert_eq!(styled("\x04FF8000,000000x")[0].1, Style::default().fg(Color::Rgb(0xff, 0x80, 0x00)).bg(Color::Rgb(0, 0, 0)));
+        // Hex colors need all six digits, otherwise the code only resets.
+        assert_eq!(styled("\x034x\x04beef cafe"), vec![
+            ("x".to_string(), Style::default().fg(Color::LightRed)),
+            ("beef cafe".to_string(), Style::default()),
+        ]);
+        assert_eq!(strip("\x04FF8000,beef"), ",beef");
+        assert_eq!(strip("\x11mono\x16rev\x1Estrike\x1Fline"), "monorevstrikeline");
+    }
+
+    #[test]
+    fn input_markers() {
+        let spans = markers("\x02hi\x034,12 there\x0F", Style::default());
+        let text: Vec<&str> = spans.iter().map(|span| span.content.as_ref()).collect();
+        assert_eq!(text, vec!["B", "hi", "C4,12", " there", "O"]);
+        assert!(spans[0].style.add_modifier.contains(Modifier::REVERSED));
+        assert_eq!(spans[3].style, Style::default().fg(Color::LightRed).bg(Color::LightBlue).add_modifier(Modifier::BOLD));
+        assert_eq!(for_key('k'), Some(COLOR));
+    }
+}
@@ -1,4 +1,5 @@
 use crate::app::{App, AppResult, Mode};
+use crate::format;
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 
 /// Handles the key events and updates the state of [`App`].
@@ -22,15 +23,22 @@ pub fn handle_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
                 app.move_cursor_right();
             }
             KeyEvent {code: KeyCode::Enter, ..} => {
-                app.send_message();
+                app.submit_input();
             }
             KeyEvent {code: KeyCode::Up, ..} => {
-                app.vertical_scroll[app.selected_tab] = app.vertical_scroll[app.selected_tab].saturating_sub(1);
-                app.vertical_scroll_state[app.selected_tab] = app.vertical_scroll_state[app.selected_tab].position(app.vertical_scroll[app.selected_tab] as usize);
+                app.scroll_up();
             }
             KeyEvent {code: KeyCode::Down, ..} => {
-                app.vertical_scroll[app.selected_tab] = app.vertical_scroll[app.selected_tab].saturating_add(1);
-                app.vertical_scroll_state[app.selected_tab] = app.vertical_scroll_state[app.selected_tab].position(app.vertical_scroll[app.selected_tab] as usize);
+                app.scroll_down();
+            }
+            KeyEvent {code: KeyCode::Char('f'), modifiers: KeyModifiers::CONTROL, ..} => {
+                app.start_search();
+            }
+            KeyEvent {code: KeyCode::Char('s'), modifiers: KeyModifiers::CONTROL, ..} => {
+                app.start_buffer_search();
+            }
+            KeyEvent {code: KeyCode::Char(c), modifiers: KeyModifiers::CONTROL, ..} if format::for_key(c).is_some() => {
+                app.enter_char(format::for_key(c).unwrap_or(c));
             }
     
             KeyEvent {code: KeyCode::Char(c), ..} => {
@@ -45,6 +53,9 @@ pub fn handle_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
             KeyEvent {code: KeyCode::Insert, ..} => {
                 app.mode = Mode::Normal;
             }
+            KeyEvent {code: KeyCode::Char(c), modifiers: KeyModifiers::CONTROL, ..} if format::for_key(c).is_some() => {
+                app.enter_char(format::for_key(c).unwrap_or(c));
+            }
             KeyEvent {code: KeyCode::Char(c), ..} => {
                 app.enter_char(c);
             }
@@ -61,15 +72,47 @@ pub fn handle_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
                 app.process_command();
             }
             KeyEvent {code: KeyCode::Up, ..} => {
-                app.vertical_scroll[app.selected_tab] = app.vertical_scroll[app.selected_tab].saturating_sub(1);
-                app.vertical_scroll_state[app.selected_tab] = app.vertical_scroll_state[app.selected_tab].position(app.vertical_scroll[app.selected_tab] as usize);
+                app.scroll_up();
             }
             KeyEvent {code: KeyCode::Down, ..} => {
-                app.vertical_scroll[app.selected_tab] = app.vertical_scroll[app.selected_tab].saturating_add(1);
-                app.vertical_scroll_state[app.selected_tab] = app.vertical_scroll_state[app.selected_tab].position(app.vertical_scroll[app.selected_tab] as usize);
+                app.scroll_down();
             }   
             _ => {}
         },
+        Mode::Search => match key_event {
+            KeyEvent {code: KeyCode::Esc, ..} => {
+                app.stop_buffer_search();
+            }
+            KeyEvent {code: KeyCode::Enter, ..} => {
+                if app.buffer_search.editing {
+                    app.buffer_search.editing = false;
+                } else {
+                    app.stop_buffer_search();
+                }
+            }
+            KeyEvent {code: KeyCode::Up, ..} => {
+                app.step_buffer_search(false);
+            }
+            KeyEvent {code: KeyCode::Down, ..} => {
+                app.step_buffer_search(true);
+            }
+            KeyEvent {code: KeyCode::Backspace, ..} if app.buffer_search.editing => {
+                app.edit_buffer_search(None);
+            }
+            KeyEvent {code: KeyCode::Char(c), ..} if app.buffer_search.editing => {
+                app.edit_buffer_search(Some(c));
+            }
+            KeyEvent {code: KeyCode::Char('n'), ..} => {
+                app.step_buffer_search(false);
+            }
+            KeyEvent {code: KeyCode::Char('N'), ..} => {
+                app.step_buffer_search(true);
+            }
+            KeyEvent {code: KeyCode::Char('/'), ..} => {
+                app.start_buffer_search();
+            }
+            _ => {}
+        },
     }
     
     Ok(())
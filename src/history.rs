@@ -0,0 +1,60 @@
+use chrono::{SecondsFormat, Utc};
+use irc::proto::Command;
+
+use crate::message::ChatLine;
+
+/// Messages fetched per page unless the `history_lines` option says otherwise.
+pub const DEFAULT_HISTORY_LINES: usize = 50;
+
+/// A `chathistory` batch being received.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistoryBatch {
+    /// Channel or nick the history belongs to.
+    pub target: String,
+    pub lines: Vec<ChatLine>,
+}
+
+/// Requests the most recent messages of a target.
+pub fn latest(target: &str, limit: usize) -> Command {
+    chathistory("LATEST", target, "*".to_string(), limit)
+}
+
+/// Requests the messages sent before a line, anchored on its `msgid`
+/// when it has one and on its time otherwise.
+pub fn before(target: &str, oldest: &ChatLine, limit: usize) -> Command {
+    let anchor = match oldest.tag("msgid") {
+        Some(msgid) => format!("msgid={}", msgid),
+        None => format!("timestamp={}", oldest.time.with_timezone(&Utc).to_rfc3339_opts(SecondsFormat::Millis, true)),
+    };
+    chathistory("BEFORE", target, anchor, limit)
+}
+
+fn chathistory(subcommand: &str, target: &str, anchor: String, limit: usize) -> Command {
+    Command::Raw("CHATHISTORY".to_string(), vec![subcommand.to_string(), target.to_string(), anchor, limit.to_string()])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::LineKind;
+    use chrono::{Local, TimeZone};
+    use irc::proto::Tag;
+
+    fn args(command: &Command) -> Vec<String> {
+        match command {
+            Command::Raw(_, args) => args.clone(),
+            _ => vec![],
+        }
+    }
+
+    #[test]
+    fn requests() {
+        assert_eq!(args(&latest("#rust", 50)), vec!["LATEST", "#rust", "*", "50"]);
+
+        let mut line = ChatLine::new(LineKind::Privmsg, Some("alice"), Some("#rust"), "hi");
+        line.time = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap().with_timezone(&Local);
+        assert_eq!(args(&before("#rust", &line, 20)), vec!["BEFORE", "#rust", "timestamp=2024-03-01T12:00:00.000Z", "20"]);
+        line.tags.push(Tag("msgid".to_string(), Some("abc".to_string())));
+        assert_eq!(args(&before("#rust", &line, 20))[2], "msgid=abc");
+    }
+}
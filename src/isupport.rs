@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Channel prefixes assumed until the server says otherwise.
+pub const DEFAULT_CHANTYPES: &str = "#&";
+
+/// Case mapping used by the server to compare nicks and channel names.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CaseMapping {
+    /// Only `A-Z` fold to `a-z`.
+    Ascii,
+    /// `A-Z[]\~` fold to `a-z{}|^`.
+    #[default]
+    Rfc1459,
+    /// `A-Z[]\` fold to `a-z{}|`.
+    StrictRfc1459,
+}
+
+impl FromStr for CaseMapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ascii" => Ok(CaseMapping::Ascii),
+            "rfc1459" => Ok(CaseMapping::Rfc1459),
+            "strict-rfc1459" => Ok(CaseMapping::StrictRfc1459),
+            _ => Err(format!("unsupported case mapping: {}", s)),
+        }
+    }
+}
+
+impl CaseMapping {
+    /// Folds a single character to its lower-case form.
+    pub fn fold(&self, c: char) -> char {
+        match (self, c) {
+            (_, 'A'..='Z') => c.to_ascii_lowercase(),
+            (CaseMapping::Rfc1459 | CaseMapping::StrictRfc1459, '[') => '{',
+            (CaseMapping::Rfc1459 | CaseMapping::StrictRfc1459, ']') => '}',
+            (CaseMapping::Rfc1459 | CaseMapping::StrictRfc1459, '\\') => '|',
+            (CaseMapping::Rfc1459, '~') => '^',
+            _ => c,
+        }
+    }
+
+    /// Folds a whole name so equal names compare equal.
+    pub fn normalize(&self, name: &str) -> String {
+        name.chars().map(|c| self.fold(c)).collect()
+    }
+
+    /// Compares two names under this case mapping.
+    pub fn eq(&self, a: &str, b: &str) -> bool {
+        a.chars().count() == b.chars().count() && a.chars().zip(b.chars()).all(|(a, b)| self.fold(a) == self.fold(b))
+    }
+}
+
+/// The four groups of the CHANMODES token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChanModes {
+    /// Modes that add or remove an address from a list, like `b`.
+    pub list: String,
+    /// Modes that always take a parameter, like `k`.
+    pub always: String,
+    /// Modes that take a parameter only when set, like `l`.
+    pub on_set: String,
+    /// Modes that never take a parameter.
+    pub never: String,
+}
+
+impl Default for ChanModes {
+    fn default() -> Self {
+        Self {
+            list: "b".to_string(),
+            always: "k".to_string(),
+            on_set: "l".to_string(),
+            never: "imnpst".to_string(),
+        }
+    }
+}
+
+/// What the server told us about itself in RPL_ISUPPORT (005).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerInfo {
+    pub chantypes: String,
+    /// Membership modes and their prefixes, highest rank first, e.g. `('o', '@')`.
+    pub prefixes: Vec<(char, char)>,
+    pub casemapping: CaseMapping,
+    pub chanmodes: ChanModes,
+    /// Longest nickname, `None` until the server advertises it.
+    pub nicklen: Option<usize>,
+    /// Maximum targets per command, `None` meaning unlimited.
+    pub targmax: HashMap<String, Option<usize>>,
+    pub network: Option<String>,
+    /// Parameterised modes allowed per MODE line.
+    pub modes: usize,
+    /// Most messages per CHATHISTORY request, `Some(0)` meaning no limit.
+    pub chathistory: Option<usize>,
+    /// Most nicks on the MONITOR list, `Some(0)` meaning no limit.
+    pub monitor: Option<usize>,
+}
+
+impl Default for ServerInfo {
+    fn default() -> Self {
+        Self {
+            chantypes: DEFAULT_CHANTYPES.to_string(),
+            prefixes: vec![('o', '@'), ('v', '+')],
+            casemapping: CaseMapping::default(),
+            chanmodes: ChanModes::default(),
+            nicklen: None,
+            targmax: HashMap::new(),
+            network: None,
+            modes: 3,
+            chathistory: None,
+            monitor: None,
+        }
+    }
+}
+
+impl ServerInfo {
+    /// Applies the tokens of an RPL_ISUPPORT reply.
+    ///
+    /// `args` are the numeric's parameters, starting with our nick and
+    /// ending with the human readable trailer.
+    pub fn update(&mut self, args: &[String]) {
+        let defaults = ServerInfo::default();
+        for token in args.iter().skip(1).filter(|token| !token.contains(' ')) {
+            if let Some(name) = token.strip_prefix('-') {
+                self.reset(name, &defaults);
+                continue;
+            }
+            let (name, value) = token.split_once('=').unwrap_or((token, ""));
+            match name {
+                "CHANTYPES" => self.chantypes = value.to_string(),
+                "PREFIX" => {
+                    if let Some((modes, prefixes)) = value.strip_prefix('(').and_then(|v| v.split_once(')')) {
+                        self.prefixes = modes.chars().zip(prefixes.chars()).collect();
+                    }
+                }
+                "CASEMAPPING" => self.casemapping = value.parse().unwrap_or_default(),
+                "CHANMODES" => {
+                    let mut groups = value.split(',').map(|group| group.to_string());
+                    self.chanmodes = ChanModes {
+                        list: groups.next().unwrap_or_default(),
+                        always: groups.next().unwrap_or_default(),
+                        on_set: groups.next().unwrap_or_default(),
+                        never: groups.next().unwrap_or_default(),
+                    };
+                }
+                "NICKLEN" => self.nicklen = value.parse().ok(),
+                "TARGMAX" => {
+                    self.targmax = value
+                        .split(',')
+                        .filter_map(|entry| entry.split_once(':'))
+                        .map(|(command, max)| (command.to_ascii_uppercase(), max.parse().ok()))
+                        .collect();
+                }
+                "NETWORK" => self.network = Some(value.to_string()),
+                // A MODES token without a value means there is no limit.
+                "MODES" => self.modes = value.parse().unwrap_or(usize::MAX),
+                "CHATHISTORY" => self.chathistory = Some(value.parse().unwrap_or(0)),
+                "MONITOR" => self.monitor = Some(value.parse().unwrap_or(0)),
+                _ => {}
+            }
+        }
+    }
+
+    fn reset(&mut self, name: &str, defaults: &ServerInfo) {
+        match name {
+            "CHANTYPES" => self.chantypes = defaults.chantypes.clone(),
+            "PREFIX" => self.prefixes = defaults.prefixes.clone(),
+            "CASEMAPPING" => self.casemapping = defaults.casemapping,
+            "CHANMODES" => self.chanmodes = defaults.chanmodes.clone(),
+            "NICKLEN" => self.nicklen = defaults.nicklen,
+            "TARGMAX" => self.targmax.clear(),
+            "NETWORK" => self.network = None,
+            "MODES" => self.modes = defaults.modes,
+            "CHATHISTORY" => self.chathistory = None,
+            "MONITOR" => self.monitor = None,
+            _ => {}
+        }
+    }
+
+    /// Whether a target names a channel rather than a user: one of the
+    /// CHANTYPES prefixes followed by a name without spaces, commas or ^G.
+    pub fn is_channel(&self, name: &str) -> bool {
+        name.len() > 1
+            && name.starts_with(|c| self.chantypes.contains(c))
+            && !name.contains([' ', ',', '\x07'])
+    }
+
+    /// Whether a word is a mode string: signed like `+b`, or only channel
+    /// mode letters like `b` or `ov`.
+    pub fn is_mode_string(&self, word: &str) -> bool {
+        let modes = &self.chanmodes;
+        let known = |c: char| [&modes.list, &modes.always, &modes.on_set, &modes.never].iter().any(|letters| letters.contains(c))
+            || self.prefixes.iter().any(|(mode, _)| *mode == c);
+        word.starts_with(['+', '-']) || (!word.is_empty() && word.chars().all(known))
+    }
+
+    /// Splits membership prefixes such as `@+` off the front of a nick.
+    pub fn split_prefixes<'a>(&self, nick: &'a str) -> (&'a str, &'a str) {
+        let start = nick
+            .find(|c| !self.prefixes.iter().any(|(_, prefix)| *prefix == c))
+            .unwrap_or(nick.len());
+        nick.split_at(start)
+    }
+
+    /// Folds a nick or channel name for use as a lookup key.
+    pub fn normalize(&self, name: &str) -> String {
+        self.casemapping.normalize(name)
+    }
+
+    /// Compares two nicks or channel names the way the server does.
+    pub fn eq(&self, a: &str, b: &str) -> bool {
+        self.casemapping.eq(a, b)
+    }
+
+    /// Maximum number of targets the server accepts for a command.
+    pub fn max_targets(&self, command: &str) -> Option<usize> {
+        self.targmax.get(command).copied().flatten()
+    }
+
+    /// Splits channel mode arguments such as `+ov-k alice bob key` into
+    /// single changes, pairing each mode with its parameter.
+    pub fn mode_changes(&self, tokens: &[String]) -> Vec<ModeChange> {
+        let mut changes = vec![];
+        let mut params: Vec<&String> = vec![];
+        let mut modestrings: Vec<&String> = vec![];
+        // Modestrings and parameters may be interleaved (`+o a +v b`).
+        for token in tokens {
+            if token.starts_with(['+', '-']) {
+                modestrings.push(token);
+            } else {
+                params.push(token);
+            }
+        }
+        let mut params = params.into_iter();
+        for modestring in modestrings {
+            let mut adding = true;
+            for mode in modestring.chars() {
+                match mode {
+                    '+' => adding = true,
+                    '-' => adding = false,
+                    _ => {
+                        let takes_param = self.prefixes.iter().any(|(prefix_mode, _)| *prefix_mode == mode)
+                            || self.chanmodes.list.contains(mode)
+                            || self.chanmodes.always.contains(mode)
+                            || (adding && self.chanmodes.on_set.contains(mode));
+                        let param = if takes_param { params.next().cloned() } else { None };
+                        changes.push(ModeChange { adding, mode, param });
+                    }
+                }
+            }
+        }
+        changes
+    }
+
+    /// Prefix character of a membership mode, like `@` for `o`.
+    pub fn prefix_for(&self, mode: char) -> Option<char> {
+        self.prefixes.iter().find(|(prefix_mode, _)| *prefix_mode == mode).map(|(_, prefix)| *prefix)
+    }
+}
+
+/// A single channel mode being set or unset.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModeChange {
+    pub adding: bool,
+    pub mode: char,
+    pub param: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(line: &str) -> Vec<String> {
+        let mut args = vec!["me".to_string()];
+        args.extend(line.split(' ').map(|token| token.to_string()));
+        args.push("are supported by this server".to_string());
+        args
+    }
+
+    #[test]
+    fn parses_tokens() {
+        let mut info = ServerInfo::default();
+        info.update(&tokens("CHANTYPES=#& PREFIX=(qaohv)~&@%+ CASEMAPPING=ascii CHANMODES=beI,k,l,imnpstr NICKLEN=30 TARGMAX=PRIVMSG:4,JOIN: NETWORK=Libera.Chat MODES=4 CHATHISTORY=100 MONITOR=100"));
+        assert!(info.is_channel("&local"));
+        assert!(!info.is_channel("nick#1"));
+        assert_eq!(info.prefixes[0], ('q', '~'));
+        assert_eq!(info.prefixes.len(), 5);
+        assert_eq!(info.casemapping, CaseMapping::Ascii);
+        assert_eq!(info.chanmodes.list, "beI");
+        assert_eq!(info.nicklen, Some(30));
+        assert_eq!(info.max_targets("PRIVMSG"), Some(4));
+        assert_eq!(info.max_targets("JOIN"), None);
+        assert_eq!(info.network.as_deref(), Some("Libera.Chat"));
+        assert_eq!(info.modes, 4);
+        assert_eq!(info.chathistory, Some(100));
+        assert_eq!(info.monitor, Some(100));
+
+        info.update(&tokens("-CHANTYPES -NETWORK MODES"));
+        assert_eq!(info.chantypes, "#&");
+        assert_eq!(info.network, None);
+        assert_eq!(info.modes, usize::MAX);
+    }
+
+    #[test]
+    fn mode_changes() {
+        let info = ServerInfo::default();
+        let tokens: Vec<String> = ["+ol-k", "alice", "10", "key"].iter().map(|t| t.to_string()).collect();
+        let changes = info.mode_changes(&tokens);
+        assert_eq!(changes, vec![
+            ModeChange { adding: true, mode: 'o', param: Some("alice".to_string()) },
+            ModeChange { adding: true, mode: 'l', param: Some("10".to_string()) },
+            ModeChange { adding: false, mode: 'k', param: Some("key".to_string()) },
+        ]);
+        let tokens: Vec<String> = ["+o", "a", "-v", "b", "-l"].iter().map(|t| t.to_string()).collect();
+        let changes = info.mode_changes(&tokens);
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[1], ModeChange { adding: false, mode: 'v', param: Some("b".to_string()) });
+        assert_eq!(changes[2].param, None);
+
+        assert!(info.is_mode_string("+b") && info.is_mode_string("b") && info.is_mode_string("ov"));
+        assert!(!info.is_mode_string("alice") && !info.is_mode_string("#rust") && !info.is_mode_string(""));
+    }
+
+    #[test]
+    fn splits_prefixes() {
+        let info = ServerInfo::default();
+        assert_eq!(info.split_prefixes("@+alice"), ("@+", "alice"));
+        assert_eq!(info.split_prefixes("bob"), ("", "bob"));
+    }
+
+    #[test]
+    fn case_mappings() {
+        assert!(CaseMapping::Rfc1459.eq("Nick[a]~", "nick{A}^"));
+        assert!(!CaseMapping::StrictRfc1459.eq("nick~", "nick^"));
+        assert!(CaseMapping::StrictRfc1459.eq("[\\]", "{|}"));
+        assert!(!CaseMapping::Ascii.eq("[", "{"));
+        assert_eq!(CaseMapping::Ascii.normalize("#Rust"), "#rust");
+    }
+}
@@ -1,6 +1,48 @@
 /// Application.
 pub mod app;
 
+/// Scrollback line model.
+pub mod message;
+
+/// Chat buffers.
+pub mod buffer;
+
+/// Slash-command registry.
+pub mod command;
+
+/// Server feature advertisement (RPL_ISUPPORT).
+pub mod isupport;
+
+/// Channel operator helpers.
+pub mod operator;
+
+/// SASL authentication.
+pub mod sasl;
+
+/// IRCv3 capability negotiation.
+pub mod cap;
+
+/// Server-side scrollback (CHATHISTORY).
+pub mod history;
+
+/// Chat logs on disk.
+pub mod logger;
+
+/// Scrollback and log search.
+pub mod search;
+
+/// Connection state and reconnection backoff.
+pub mod connection;
+
+/// Nickname fallbacks and recovery.
+pub mod nick;
+
+/// mIRC text formatting.
+pub mod format;
+
+/// Per-nick colors.
+pub mod colors;
+
 /// Terminal events handler.
 pub mod event;
 
@@ -0,0 +1,454 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, SecondsFormat, TimeZone};
+use irc::client::data::Config;
+use irc::proto::Tag;
+use serde_json::{json, Map, Value};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+use crate::message::{ChatLine, LineKind};
+
+/// Lines loaded from the log when a buffer opens, unless the `backlog_lines` option says otherwise.
+pub const DEFAULT_BACKLOG_LINES: usize = 100;
+
+/// How log lines are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Timestamped lines as shown in the interface.
+    #[default]
+    Text,
+    /// One JSON object per line, keeping the message tags.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" | "plain" => Ok(LogFormat::Text),
+            "json" | "jsonl" => Ok(LogFormat::Json),
+            _ => Err(format!("Unknown log_format: {}", s)),
+        }
+    }
+}
+
+impl LogFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            LogFormat::Text => "log",
+            LogFormat::Json => "jsonl",
+        }
+    }
+}
+
+/// When a log file is moved aside and a new one started.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Rotation {
+    /// When the first line of a new day is written.
+    #[default]
+    Daily,
+    /// Before the file grows past this many bytes.
+    Size(u64),
+    Never,
+}
+
+impl FromStr for Rotation {
+    type Err = String;
+
+    /// Parses `daily`, `never` or a size such as `512K`, `10M` or `1G`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        match s.as_str() {
+            "daily" => return Ok(Rotation::Daily),
+            "never" | "none" => return Ok(Rotation::Never),
+            _ => {}
+        }
+        let (digits, unit) = match s.trim_end_matches('b').char_indices().last() {
+            Some((i, unit @ ('k' | 'm' | 'g'))) => (&s[..i], unit),
+            _ => (s.trim_end_matches('b'), ' '),
+        };
+        let multiplier = match unit {
+            'k' => 1 << 10,
+            'm' => 1 << 20,
+            'g' => 1 << 30,
+            _ => 1,
+        };
+        match digits.trim().parse::<u64>() {
+            Ok(size) if size > 0 => Ok(Rotation::Size(size * multiplier)),
+            _ => Err(format!("Unknown log_rotate: {}", s)),
+        }
+    }
+}
+
+/// A line on its way to disk.
+#[derive(Debug)]
+struct LogEntry {
+    path: PathBuf,
+    time: DateTime<Local>,
+    text: String,
+}
+
+/// Writes buffer lines to per-network, per-buffer files in the background.
+#[derive(Debug)]
+pub struct Logger {
+    pub dir: PathBuf,
+    pub format: LogFormat,
+    pub rotation: Rotation,
+    /// Buffers that are never logged.
+    pub exclude: Vec<String>,
+    sender: mpsc::UnboundedSender<LogEntry>,
+}
+
+impl Logger {
+    /// Starts the writer task.
+    pub fn new(dir: PathBuf, format: LogFormat, rotation: Rotation, exclude: Vec<String>) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(write_entries(receiver, rotation));
+        Self { dir, format, rotation, exclude, sender }
+    }
+
+    /// Builds a logger from the `logging`, `log_dir`, `log_format`,
+    /// `log_rotate` and `log_exclude` options. Returns `None` when logging is off.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        if config.get_option("logging").is_some_and(|logging| logging == "false") {
+            return None;
+        }
+        let dir = match config.get_option("log_dir") {
+            Some(dir) => PathBuf::from(dir),
+            None => default_dir()?,
+        };
+        let format = config.get_option("log_format").and_then(|format| format.parse().ok()).unwrap_or_default();
+        let rotation = config.get_option("log_rotate").and_then(|rotation| rotation.parse().ok()).unwrap_or_default();
+        let exclude = config.get_option("log_exclude")
+            .map(|exclude| exclude.split([' ', ',']).filter(|name| !name.is_empty()).map(|name| name.to_string()).collect())
+            .unwrap_or_default();
+        Some(Self::new(dir, format, rotation, exclude))
+    }
+
+    /// Path of the current log file of a buffer.
+    pub fn path(&self, network: &str, buffer: &str) -> PathBuf {
+        self.dir.join(file_name(network)).join(format!("{}.{}", file_name(buffer), self.format.extension()))
+    }
+
+    /// Log files of a buffer, newest first: the current one, then the rotated ones.
+    pub fn files(&self, network: &str, buffer: &str) -> Vec<PathBuf> {
+        let current = self.path(network, buffer);
+        let stem = file_name(buffer);
+        let extension = self.format.extension();
+        let mut rotated: Vec<PathBuf> = current.parent()
+            .and_then(|dir| std::fs::read_dir(dir).ok())
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+                // Only stamps after the stem, so `#rust` does not pick up `#rust.dev`.
+                name.strip_prefix(&stem)
+                    .and_then(|rest| rest.strip_prefix('.'))
+                    .and_then(|rest| rest.strip_suffix(extension))
+                    .and_then(|rest| rest.strip_suffix('.'))
+                    .is_some_and(|stamp| !stamp.is_empty() && stamp.chars().all(|c| c.is_ascii_digit() || c == '-'))
+            })
+            .collect();
+        rotated.sort();
+        rotated.reverse();
+        std::iter::once(current).chain(rotated).collect()
+    }
+
+    /// Every log file of a network with the buffer it belongs to, as written
+    /// to disk (case folded and made safe for file names).
+    pub fn network_files(&self, network: &str) -> Vec<(String, PathBuf)> {
+        let extension = format!(".{}", self.format.extension());
+        let mut files: Vec<(String, PathBuf)> = std::fs::read_dir(self.dir.join(file_name(network)))
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter_map(|path| {
+                let name = path.file_name()?.to_str()?.strip_suffix(&extension)?;
+                let buffer = match name.rsplit_once('.') {
+                    Some((buffer, stamp)) if !stamp.is_empty() && stamp.chars().all(|c| c.is_ascii_digit() || c == '-') => buffer,
+                    _ => name,
+                };
+                Some((buffer.to_string(), path))
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    /// Reads every line of a log file.
+    pub fn read(&self, path: &Path) -> Vec<ChatLine> {
+        std::fs::read_to_string(path)
+            .map(|contents| contents.lines().filter_map(|entry| parse_entry(self.format, entry)).collect())
+            .unwrap_or_default()
+    }
+
+    /// Reads up to `limit` logged lines of a buffer older than `before`, or
+    /// the latest ones without it, in chronological order. Lines sharing the
+    /// oldest second are kept together so the next page does not skip any.
+    pub fn backlog(&self, network: &str, buffer: &str, before: Option<DateTime<Local>>, limit: usize) -> Vec<ChatLine> {
+        let mut lines: Vec<ChatLine> = vec![];
+        'files: for path in self.files(network, buffer) {
+            let Ok(contents) = std::fs::read_to_string(&path) else {
+                continue;
+            };
+            for entry in contents.lines().rev() {
+                let Some(line) = parse_entry(self.format, entry) else {
+                    continue;
+                };
+                if before.is_some_and(|before| line.time >= before) {
+                    continue;
+                }
+                if lines.len() >= limit && lines.last().is_some_and(|last| last.time != line.time) {
+                    break 'files;
+                }
+                lines.push(line);
+            }
+        }
+        lines.reverse();
+        lines
+    }
+
+    /// Queues a line for writing.
+    pub fn log(&self, network: &str, buffer: &str, line: &ChatLine) {
+        let entry = LogEntry {
+            path: self.path(network, buffer),
+            time: line.time,
+            text: format_entry(self.format, line),
+        };
+        // The writer only stops when the logger is dropped.
+        let _ = self.sender.send(entry);
+    }
+}
+
+/// `ironirc/logs` under the XDG data home.
+pub fn default_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("ironirc").join("logs"))
+}
+
+/// Makes a network or buffer name safe to use as a file name.
+pub fn file_name(name: &str) -> String {
+    let name: String = name.to_lowercase()
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '\0' => '_',
+            c => c,
+        })
+        .collect();
+    match name.strip_prefix('.') {
+        Some(rest) => format!("_{}", rest),
+        None => name,
+    }
+}
+
+/// Name a rotated file is moved to, such as `#rust.2024-03-01.log`.
+pub fn rotated_path(path: &Path, stamp: &str) -> PathBuf {
+    let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+    path.with_file_name(format!("{}.{}.{}", stem, stamp, extension))
+}
+
+/// Name of a line kind in JSON logs.
+pub fn kind_name(kind: LineKind) -> String {
+    match kind {
+        LineKind::Numeric(numeric) => format!("{:03}", numeric),
+        kind => format!("{:?}", kind).to_lowercase(),
+    }
+}
+
+/// Line kind from its name in JSON logs.
+pub fn parse_kind(name: &str) -> LineKind {
+    match name {
+        "privmsg" => LineKind::Privmsg,
+        "notice" => LineKind::Notice,
+        "action" => LineKind::Action,
+        "join" => LineKind::Join,
+        "part" => LineKind::Part,
+        "quit" => LineKind::Quit,
+        "nick" => LineKind::Nick,
+        "kick" => LineKind::Kick,
+        "mode" => LineKind::Mode,
+        "topic" => LineKind::Topic,
+        "error" => LineKind::Error,
+        "status" => LineKind::Status,
+        name => name.parse().map(LineKind::Numeric).unwrap_or(LineKind::Other),
+    }
+}
+
+/// Formats a line for the log file, without the trailing newline.
+pub fn format_entry(format: LogFormat, line: &ChatLine) -> String {
+    match format {
+        LogFormat::Text => {
+            let time = line.time.format("%Y-%m-%d %H:%M:%S");
+            let sender = line.sender_name();
+            match line.kind {
+                LineKind::Privmsg => format!("[{}] <{}> {}", time, sender, line.text),
+                _ => format!("[{}] {}", time, crate::ui::format_line(line)),
+            }
+        },
+        LogFormat::Json => {
+            let tags: Map<String, Value> = line.tags.iter()
+                .map(|tag| (tag.0.clone(), tag.1.clone().map(Value::String).unwrap_or(Value::Null)))
+                .collect();
+            json!({
+                "time": line.time.to_rfc3339_opts(SecondsFormat::Millis, false),
+                "kind": kind_name(line.kind),
+                "sender": line.sender,
+                "target": line.target,
+                "text": line.text,
+                "tags": tags,
+            }).to_string()
+        },
+    }
+}
+
+/// Reads a line back from a log file.
+///
+/// Text logs only keep the kind of messages; other lines come back as
+/// status lines carrying their logged text.
+pub fn parse_entry(format: LogFormat, entry: &str) -> Option<ChatLine> {
+    match format {
+        LogFormat::Text => {
+            let (time, rest) = entry.strip_prefix('[')?.split_once("] ")?;
+            let time = NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S").ok()?;
+            let time = Local.from_local_datetime(&time).earliest()?;
+            // The space after the sender ends it, so nicks containing `-` stay whole in notices.
+            let message = |open: &str, close: &str| {
+                let (sender, text) = rest.strip_prefix(open)?.split_once(close)?;
+                (!sender.is_empty() && !sender.contains(' ')).then(|| (sender.to_string(), text.to_string()))
+            };
+            let (kind, sender, text) = if let Some((sender, text)) = message("<", "> ") {
+                (LineKind::Privmsg, Some(sender), text)
+            } else if let Some((sender, text)) = message("* ", " ") {
+                (LineKind::Action, Some(sender), text)
+            } else if let Some((sender, text)) = message("-", "- ") {
+                (LineKind::Notice, Some(sender), text)
+            } else {
+                let text = rest.split_once(' ')
+                    .filter(|(marker, _)| ["--", "-->", "<--", "!!"].contains(marker))
+                    .map_or(rest, |(_, text)| text);
+                (LineKind::Status, None, text.to_string())
+            };
+            let mut line = ChatLine::new(kind, sender.as_deref(), None, text);
+            line.time = time;
+            Some(line)
+        },
+        LogFormat::Json => {
+            let entry: Value = serde_json::from_str(entry).ok()?;
+            let time = DateTime::parse_from_rfc3339(entry["time"].as_str()?).ok()?.with_timezone(&Local);
+            let mut line = ChatLine::new(
+                parse_kind(entry["kind"].as_str()?),
+                entry["sender"].as_str(),
+                entry["target"].as_str(),
+                entry["text"].as_str()?,
+            );
+            line.time = time;
+            if let Some(tags) = entry["tags"].as_object() {
+                line.tags = tags.iter().map(|(key, value)| Tag(key.clone(), value.as_str().map(|value| value.to_string()))).collect();
+            }
+            Some(line)
+        },
+    }
+}
+
+/// Size and last write day of an open log file.
+struct FileState {
+    day: NaiveDate,
+    size: u64,
+}
+
+async fn write_entries(mut receiver: mpsc::UnboundedReceiver<LogEntry>, rotation: Rotation) {
+    let mut files: HashMap<PathBuf, FileState> = HashMap::new();
+    while let Some(entry) = receiver.recv().await {
+        let line = format!("{}\n", entry.text);
+        let state = match files.remove(&entry.path) {
+            Some(state) => Some(state),
+            None => tokio::fs::metadata(&entry.path).await.ok().map(|metadata| FileState {
+                day: metadata.modified().map(|modified| DateTime::<Local>::from(modified).date_naive()).unwrap_or(entry.time.date_naive()),
+                size: metadata.len(),
+            }),
+        };
+        let rotate = state.as_ref().and_then(|state| match rotation {
+            Rotation::Daily if state.day != entry.time.date_naive() => Some(state.day.format("%Y-%m-%d").to_string()),
+            Rotation::Size(limit) if state.size > 0 && state.size + line.len() as u64 > limit => {
+                Some(entry.time.format("%Y-%m-%d-%H%M%S").to_string())
+            },
+            _ => None,
+        });
+        let mut size = state.map(|state| state.size).unwrap_or(0);
+        if let Some(stamp) = rotate {
+            if tokio::fs::rename(&entry.path, rotated_path(&entry.path, &stamp)).await.is_ok() {
+                size = 0;
+            }
+        }
+        if let Some(parent) = entry.path.parent() {
+            let _ = tokio::fs::create_dir_all(parent).await;
+        }
+        let file = tokio::fs::OpenOptions::new().create(true).append(true).open(&entry.path).await;
+        // Logging is best effort, a failed write must not take the client down.
+        if let Ok(mut file) = file {
+            if file.write_all(line.as_bytes()).await.is_ok() {
+                size += line.len() as u64;
+            }
+        }
+        files.insert(entry.path, FileState { day: entry.time.date_naive(), size });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options() {
+        assert_eq!("daily".parse(), Ok(Rotation::Daily));
+        assert_eq!("10M".parse(), Ok(Rotation::Size(10 << 20)));
+        assert_eq!("512kb".parse(), Ok(Rotation::Size(512 << 10)));
+        assert_eq!("4096".parse(), Ok(Rotation::Size(4096)));
+        assert!("often".parse::<Rotation>().is_err());
+        assert_eq!("JSON".parse(), Ok(LogFormat::Json));
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(file_name("#Rust/Dev"), "#rust_dev");
+        assert_eq!(file_name("..."), "_..");
+        let path = PathBuf::from("logs/libera.chat/#rust.log");
+        assert_eq!(rotated_path(&path, "2024-03-01"), PathBuf::from("logs/libera.chat/#rust.2024-03-01.log"));
+    }
+
+    #[test]
+    fn entries() {
+        let mut line = ChatLine::new(LineKind::Privmsg, Some("alice"), Some("#rust"), "hi");
+        line.time = Local.with_ymd_and_hms(2024, 3, 1, 12, 30, 5).unwrap();
+        assert_eq!(format_entry(LogFormat::Text, &line), "[2024-03-01 12:30:05] <alice> hi");
+        line.tags.push(Tag("msgid".to_string(), Some("abc".to_string())));
+        let entry: Value = serde_json::from_str(&format_entry(LogFormat::Json, &line)).unwrap();
+        assert_eq!(entry["kind"], "privmsg");
+        assert_eq!(entry["tags"]["msgid"], "abc");
+
+        line.kind = LineKind::Action;
+        assert_eq!(format_entry(LogFormat::Text, &line), "[2024-03-01 12:30:05] * alice hi");
+    }
+
+    #[test]
+    fn entries_read_back() {
+        let mut line = ChatLine::new(LineKind::Notice, Some("alice"), Some("#rust"), "hi there");
+        line.time = Local.with_ymd_and_hms(2024, 3, 1, 12, 30, 5).unwrap();
+        line.tags.push(Tag("msgid".to_string(), Some("abc".to_string())));
+        let parsed = parse_entry(LogFormat::Text, &format_entry(LogFormat::Text, &line)).unwrap();
+        assert_eq!((parsed.kind, parsed.sender.as_deref(), parsed.text.as_str(), parsed.time), (LineKind::Notice, Some("alice"), "hi there", line.time));
+        assert_eq!(parse_entry(LogFormat::Json, &format_entry(LogFormat::Json, &line)).unwrap(), line);
+
+        let notice = parse_entry(LogFormat::Text, "[2024-03-01 12:30:06] -foo-bar- see -x- here").unwrap();
+        assert_eq!((notice.kind, notice.sender.as_deref(), notice.text.as_str()), (LineKind::Notice, Some("foo-bar"), "see -x- here"));
+
+        let join = parse_entry(LogFormat::Text, "[2024-03-01 12:31:00] --> bob joined #rust").unwrap();
+        assert_eq!((join.kind, join.text.as_str()), (LineKind::Status, "bob joined #rust"));
+        assert!(parse_entry(LogFormat::Text, "garbage").is_none());
+    }
+}
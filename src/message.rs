@@ -0,0 +1,261 @@
+use chrono::{DateTime, Local};
+use irc::proto::{Command, Message, Prefix, Response, Tag};
+
+/// What a scrollback line represents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineKind {
+    Privmsg,
+    Notice,
+    Action,
+    Join,
+    Part,
+    Quit,
+    Nick,
+    Kick,
+    Mode,
+    Topic,
+    Numeric(u16),
+    Error,
+    /// Generated by the client itself, such as command feedback.
+    Status,
+    /// Marks where lines loaded from the log end and live traffic starts.
+    Separator,
+    Other,
+}
+
+/// Whether the server has confirmed a line we sent.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Delivery {
+    /// Received from the server, or sent without echo-message.
+    #[default]
+    Confirmed,
+    /// Sent, waiting for the server to echo it back.
+    Pending,
+    /// Rejected by the server.
+    Failed,
+}
+
+/// A single line of scrollback, built from an [`irc::proto::Message`]
+/// or produced locally by the client.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChatLine {
+    pub time: DateTime<Local>,
+    pub kind: LineKind,
+    /// Nickname or server name the line came from.
+    pub sender: Option<String>,
+    /// Channel or nickname the line was addressed to.
+    pub target: Option<String>,
+    pub text: String,
+    pub tags: Vec<Tag>,
+    pub delivery: Delivery,
+}
+
+impl ChatLine {
+    /// Constructs a new line stamped with the local time.
+    pub fn new(kind: LineKind, sender: Option<&str>, target: Option<&str>, text: impl Into<String>) -> Self {
+        Self {
+            time: Local::now(),
+            kind,
+            sender: sender.map(|s| s.to_string()),
+            target: target.map(|t| t.to_string()),
+            text: text.into(),
+            tags: vec![],
+            delivery: Delivery::Confirmed,
+        }
+    }
+
+    /// Converts a protocol message into a scrollback line.
+    pub fn from_message(message: &Message) -> Self {
+        let sender = match &message.prefix {
+            Some(Prefix::Nickname(nick, _, _)) => Some(nick.as_str()),
+            Some(Prefix::ServerName(name)) => Some(name.as_str()),
+            None => None,
+        };
+        let (kind, target, text) = match &message.command {
+            Command::PRIVMSG(target, text) => match ctcp_action(text) {
+                Some(action) => (LineKind::Action, Some(target.as_str()), action.to_string()),
+                None => (LineKind::Privmsg, Some(target.as_str()), text.clone()),
+            },
+            Command::NOTICE(target, text) => (LineKind::Notice, Some(target.as_str()), text.clone()),
+            Command::JOIN(channel, _, _) => (LineKind::Join, Some(channel.as_str()), String::new()),
+            Command::PART(channel, reason) => (LineKind::Part, Some(channel.as_str()), reason.clone().unwrap_or_default()),
+            Command::QUIT(reason) => (LineKind::Quit, None, reason.clone().unwrap_or_default()),
+            Command::NICK(nick) => (LineKind::Nick, None, nick.clone()),
+            Command::KICK(channel, nick, reason) => {
+                let text = match reason {
+                    Some(reason) => format!("{} {}", nick, reason),
+                    None => nick.clone(),
+                };
+                (LineKind::Kick, Some(channel.as_str()), text)
+            },
+            Command::TOPIC(channel, topic) => (LineKind::Topic, Some(channel.as_str()), topic.clone().unwrap_or_default()),
+            Command::ChannelMODE(target, _) | Command::UserMODE(target, _) => {
+                let params = command_params(&message.command);
+                (LineKind::Mode, Some(target.as_str()), params.iter().skip(2).cloned().collect::<Vec<String>>().join(" "))
+            },
+            Command::ERROR(text) => (LineKind::Error, None, text.clone()),
+            Command::Response(response, args) => match channel_numeric(*response, args) {
+                Some((channel, text)) => (LineKind::Numeric(*response as u16), Some(channel), text),
+                None => {
+                    // The first argument of a numeric is always our own nickname.
+                    let text = args.iter().skip(1).cloned().collect::<Vec<String>>().join(" ");
+                    (LineKind::Numeric(*response as u16), None, text)
+                },
+            },
+            command => {
+                let params = command_params(command);
+                let kind = match params[0].parse::<u16>() {
+                    Ok(numeric) => LineKind::Numeric(numeric),
+                    Err(_) => LineKind::Other,
+                };
+                (kind, None, params.join(" "))
+            },
+        };
+        let tags = message.tags.clone().unwrap_or_default();
+        Self {
+            // Bouncer playback carries the original time in the server-time tag.
+            time: server_time(&tags).unwrap_or_else(Local::now),
+            kind,
+            sender: sender.map(|s| s.to_string()),
+            target: target.map(|t| t.to_string()),
+            text,
+            tags,
+            delivery: Delivery::Confirmed,
+        }
+    }
+
+    /// Looks up the value of an IRCv3 message tag.
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags.iter().find(|Tag(k, _)| k == key).and_then(|Tag(_, v)| v.as_deref())
+    }
+
+    /// Nickname or server the line came from, or an empty string.
+    pub fn sender_name(&self) -> &str {
+        self.sender.as_deref().unwrap_or("")
+    }
+}
+
+/// Finds the channel a numeric reply is about and renders it readably.
+///
+/// Returns `None` for numerics that are not about a channel.
+pub fn channel_numeric(response: Response, args: &[String]) -> Option<(&str, String)> {
+    let arg = |index: usize| args.get(index).map(|arg| arg.as_str()).unwrap_or("");
+    let text = match response {
+        Response::RPL_TOPIC => format!("Topic for {}: {}", arg(1), arg(2)),
+        Response::RPL_NOTOPIC => format!("No topic is set for {}", arg(1)),
+        Response::RPL_TOPICWHOTIME => {
+            let set_at = arg(3).parse::<i64>().ok()
+                .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0))
+                .map(|time| time.with_timezone(&Local).format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_else(|| arg(3).to_string());
+            format!("Topic set by {} on {}", arg(2), set_at)
+        },
+        Response::RPL_NAMREPLY => return Some((arg(2), format!("Users on {}: {}", arg(2), arg(3)))),
+        Response::RPL_ENDOFNAMES => format!("End of user list for {}", arg(1)),
+        Response::RPL_CHANNELMODEIS => format!("Modes for {}: {}", arg(1), args[2.min(args.len())..].join(" ")),
+        Response::RPL_BANLIST | Response::RPL_INVITELIST | Response::RPL_EXCEPTLIST => {
+            let list = match response {
+                Response::RPL_BANLIST => "Ban",
+                Response::RPL_INVITELIST => "Invite exception",
+                _ => "Ban exception",
+            };
+            match args.get(3) {
+                Some(setter) => format!("{}: {} (set by {})", list, arg(2), setter),
+                None => format!("{}: {}", list, arg(2)),
+            }
+        },
+        Response::RPL_ENDOFBANLIST | Response::RPL_ENDOFINVITELIST | Response::RPL_ENDOFEXCEPTLIST => arg(2).to_string(),
+        Response::RPL_INVITING => return Some((arg(2), format!("Invited {} to {}", arg(1), arg(2)))),
+        Response::ERR_CANNOTSENDTOCHAN | Response::ERR_NOTONCHANNEL | Response::ERR_CHANOPRIVSNEEDED => {
+            format!("{}: {}", arg(1), arg(2))
+        },
+        _ => return None,
+    };
+    Some((arg(1), text))
+}
+
+/// Reads the IRCv3 `time` tag, an RFC 3339 UTC timestamp such as
+/// `2024-03-01T12:30:00.000Z`.
+pub fn server_time(tags: &[Tag]) -> Option<DateTime<Local>> {
+    let Tag(_, value) = tags.iter().find(|Tag(key, _)| key == "time")?;
+    DateTime::parse_from_rfc3339(value.as_deref()?).ok().map(|time| time.with_timezone(&Local))
+}
+
+/// Extracts the body of a CTCP ACTION (`/me`) message.
+pub fn ctcp_action(text: &str) -> Option<&str> {
+    let body = text.strip_prefix('\x01')?;
+    let body = body.strip_suffix('\x01').unwrap_or(body);
+    match body {
+        "ACTION" => Some(""),
+        _ => body.strip_prefix("ACTION "),
+    }
+}
+
+/// Splits a command into its name followed by its parameters,
+/// with the colon stripped from the trailing parameter.
+pub fn command_params(command: &Command) -> Vec<String> {
+    let raw = String::from(command);
+    let raw = raw.trim_end_matches(['\r', '\n']);
+    let (head, trailing) = match raw.find(" :") {
+        Some(index) => (&raw[..index], Some(&raw[index + 2..])),
+        None => (raw, None),
+    };
+    let mut params: Vec<String> = head.split(' ').filter(|p| !p.is_empty()).map(|p| p.to_string()).collect();
+    if let Some(trailing) = trailing {
+        params.push(trailing.to_string());
+    }
+    if params.is_empty() {
+        params.push(String::new());
+    }
+    params
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn server_time_tag() {
+        let message = Message {
+            tags: Some(vec![Tag("time".to_string(), Some("2024-03-01T12:30:05.123Z".to_string()))]),
+            prefix: Some(Prefix::Nickname("alice".to_string(), "a".to_string(), "host".to_string())),
+            command: Command::PRIVMSG("#rust".to_string(), "hi".to_string()),
+        };
+        let line = ChatLine::from_message(&message);
+        assert_eq!(line.time, Utc.with_ymd_and_hms(2024, 3, 1, 12, 30, 5).unwrap() + chrono::Duration::milliseconds(123));
+        assert_eq!(server_time(&[Tag("time".to_string(), Some("yesterday".to_string()))]), None);
+        assert_eq!(server_time(&[]), None);
+    }
+
+    fn from(nick: &str, command: Command) -> ChatLine {
+        ChatLine::from_message(&Message {
+            tags: None,
+            prefix: Some(Prefix::Nickname(nick.to_string(), "user".to_string(), "host".to_string())),
+            command,
+        })
+    }
+
+    #[test]
+    fn actions() {
+        assert_eq!(ctcp_action("\x01ACTION waves\x01"), Some("waves"));
+        assert_eq!(ctcp_action("\x01ACTION waves"), Some("waves"));
+        assert_eq!(ctcp_action("\x01ACTION\x01"), Some(""));
+        assert_eq!(ctcp_action("\x01ACTIONS waves\x01"), None);
+        assert_eq!(ctcp_action("ACTION waves"), None);
+    }
+
+    #[test]
+    fn lines_from_messages() {
+        let line = from("alice", Command::PRIVMSG("#rust".to_string(), "\x01ACTION waves\x01".to_string()));
+        assert_eq!((line.kind, line.sender.as_deref(), line.target.as_deref(), line.text.as_str()), (LineKind::Action, Some("alice"), Some("#rust"), "waves"));
+        let line = from("alice", Command::PRIVMSG("#rust".to_string(), "\x01ACTIONS\x01".to_string()));
+        assert_eq!(line.kind, LineKind::Privmsg);
+        let line = from("op", Command::KICK("#rust".to_string(), "bob".to_string(), Some("spam".to_string())));
+        assert_eq!((line.kind, line.text.as_str()), (LineKind::Kick, "bob spam"));
+        let line = from("bob", Command::QUIT(None));
+        assert_eq!((line.kind, line.target, line.text.as_str()), (LineKind::Quit, None, ""));
+        let line = from("server", Command::Response(Response::RPL_WELCOME, vec!["me".to_string(), "Welcome".to_string()]));
+        assert_eq!((line.kind, line.text.as_str()), (LineKind::Numeric(1), "Welcome"));
+    }
+}
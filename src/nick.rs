@@ -0,0 +1,92 @@
+use std::str::FromStr;
+
+use irc::proto::Command;
+
+/// Suffixes appended to the primary nickname once the alternates are used up.
+const SUFFIXES: &[&str] = &["_", "__", "1", "2", "3", "4", "5", "6", "7", "8", "9"];
+
+/// Nicknames to try after the primary one: the configured alternates, then
+/// the primary with a suffix, shortened to fit `nicklen` when it is known.
+pub fn candidates(primary: &str, alternates: &[String], nicklen: Option<usize>) -> Vec<String> {
+    let suffixed = SUFFIXES.iter().map(|suffix| {
+        let keep = nicklen.map_or(usize::MAX, |nicklen| nicklen.saturating_sub(suffix.len()).max(1));
+        format!("{}{}", primary.chars().take(keep).collect::<String>(), suffix)
+    });
+    let mut candidates: Vec<String> = vec![];
+    for nick in alternates.iter().cloned().chain(suffixed) {
+        if nick != primary && !candidates.contains(&nick) {
+            candidates.push(nick);
+        }
+    }
+    candidates
+}
+
+/// NickServ command used to take the primary nickname back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Regain {
+    /// Disconnects the session holding the nickname, then changes to it.
+    Ghost,
+    /// Moves the nickname over to us in one step.
+    Regain,
+    /// Like GHOST on services that call it RECOVER.
+    Recover,
+}
+
+impl FromStr for Regain {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ghost" => Ok(Regain::Ghost),
+            "regain" => Ok(Regain::Regain),
+            "recover" => Ok(Regain::Recover),
+            _ => Err(format!("Unknown nick_regain command: {}", s)),
+        }
+    }
+}
+
+impl Regain {
+    /// Messages to NickServ, followed by a nick change where services do not make it.
+    pub fn commands(&self, nick: &str, password: &str) -> Vec<Command> {
+        let verb = match self {
+            Regain::Ghost => "GHOST",
+            Regain::Regain => "REGAIN",
+            Regain::Recover => "RECOVER",
+        };
+        let text = match password.is_empty() {
+            true => format!("{} {}", verb, nick),
+            false => format!("{} {} {}", verb, nick, password),
+        };
+        let mut commands = vec![Command::PRIVMSG("NickServ".to_string(), text)];
+        if *self != Regain::Regain {
+            commands.push(Command::NICK(nick.to_string()));
+        }
+        commands
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alternates_then_suffixes() {
+        let nicks = candidates("ferris", &["crab".to_string(), "ferris".to_string()], Some(30));
+        assert_eq!(&nicks[..4], ["crab", "ferris_", "ferris__", "ferris1"]);
+        assert_eq!(nicks.len(), 1 + SUFFIXES.len());
+
+        let nicks = candidates("rustacean", &[], Some(9));
+        assert_eq!(&nicks[..3], ["rustacea_", "rustace__", "rustacea1"]);
+
+        let nicks = candidates("rustacean", &[], None);
+        assert_eq!(&nicks[..3], ["rustacean_", "rustacean__", "rustacean1"]);
+    }
+
+    #[test]
+    fn regain_commands() {
+        assert_eq!(Regain::Regain.commands("ferris", ""), vec![Command::PRIVMSG("NickServ".to_string(), "REGAIN ferris".to_string())]);
+        let ghost = Regain::Ghost.commands("ferris", "hunter2");
+        assert_eq!(ghost[0], Command::PRIVMSG("NickServ".to_string(), "GHOST ferris hunter2".to_string()));
+        assert_eq!(ghost[1], Command::NICK("ferris".to_string()));
+    }
+}
@@ -0,0 +1,110 @@
+use std::str::FromStr;
+
+use irc::proto::Command;
+
+/// Which parts of `nick!user@host` a derived ban mask keeps.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BanMaskStyle {
+    /// `*!*@host`
+    #[default]
+    Host,
+    /// `*!*@*.example.com`, dropping the first label of the host.
+    Domain,
+    /// `*!*user@host`, matching the user with or without a leading `~`.
+    UserHost,
+    /// `nick!*@*`
+    Nick,
+    /// `nick!user@host`
+    Full,
+}
+
+impl FromStr for BanMaskStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "host" => Ok(BanMaskStyle::Host),
+            "domain" => Ok(BanMaskStyle::Domain),
+            "user_host" => Ok(BanMaskStyle::UserHost),
+            "nick" => Ok(BanMaskStyle::Nick),
+            "full" => Ok(BanMaskStyle::Full),
+            _ => Err(format!("unknown ban mask style: {}", s)),
+        }
+    }
+}
+
+/// Builds a ban mask for a nick whose user and host may be known.
+///
+/// Without a cached host the mask falls back to `nick!*@*`.
+pub fn ban_mask(nick: &str, userhost: Option<(&str, &str)>, style: BanMaskStyle) -> String {
+    let Some((user, host)) = userhost else {
+        return format!("{}!*@*", nick);
+    };
+    match style {
+        BanMaskStyle::Host => format!("*!*@{}", host),
+        BanMaskStyle::Domain => match host.split_once('.') {
+            // Keep IP addresses intact, wildcarding them would ban far too much.
+            Some((_, domain)) if domain.contains('.') && !host.chars().all(|c| c.is_ascii_digit() || c == '.') => {
+                format!("*!*@*.{}", domain)
+            }
+            _ => format!("*!*@{}", host),
+        },
+        BanMaskStyle::UserHost => format!("*!*{}@{}", user.trim_start_matches('~'), host),
+        BanMaskStyle::Nick => format!("{}!*@*", nick),
+        BanMaskStyle::Full => format!("{}!{}@{}", nick, user, host),
+    }
+}
+
+/// Whether an argument already is a mask rather than a nickname.
+pub fn is_mask(arg: &str) -> bool {
+    arg.contains(['!', '@', '*', '?'])
+}
+
+/// Builds MODE lines applying the same mode to every parameter,
+/// at most `max_modes` per line, e.g. `MODE #chan +ooo a b c`.
+pub fn batch_modes(channel: &str, sign: char, mode: char, params: &[String], max_modes: usize) -> Vec<Command> {
+    params
+        .chunks(max_modes.max(1))
+        .map(|chunk| {
+            let mut modes = sign.to_string();
+            modes.extend(std::iter::repeat(mode).take(chunk.len()));
+            let mut args = vec![channel.to_string(), modes];
+            args.extend(chunk.iter().cloned());
+            Command::Raw("MODE".to_string(), args)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(command: &Command) -> Vec<String> {
+        match command {
+            Command::Raw(_, args) => args.clone(),
+            _ => vec![],
+        }
+    }
+
+    #[test]
+    fn batches_respect_limit() {
+        let nicks: Vec<String> = ["a", "b", "c", "d"].iter().map(|n| n.to_string()).collect();
+        let lines = batch_modes("#chan", '+', 'o', &nicks, 3);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(args(&lines[0]), vec!["#chan", "+ooo", "a", "b", "c"]);
+        assert_eq!(args(&lines[1]), vec!["#chan", "+o", "d"]);
+        assert_eq!(batch_modes("#chan", '-', 'v', &nicks, 0).len(), 4);
+    }
+
+    #[test]
+    fn mask_styles() {
+        let userhost = Some(("~bob", "host-1.example.com"));
+        assert_eq!(ban_mask("bob", userhost, BanMaskStyle::Host), "*!*@host-1.example.com");
+        assert_eq!(ban_mask("bob", userhost, BanMaskStyle::Domain), "*!*@*.example.com");
+        assert_eq!(ban_mask("bob", userhost, BanMaskStyle::UserHost), "*!*bob@host-1.example.com");
+        assert_eq!(ban_mask("bob", userhost, BanMaskStyle::Nick), "bob!*@*");
+        assert_eq!(ban_mask("bob", userhost, BanMaskStyle::Full), "bob!~bob@host-1.example.com");
+        assert_eq!(ban_mask("bob", Some(("bob", "192.0.2.1")), BanMaskStyle::Domain), "*!*@192.0.2.1");
+        assert_eq!(ban_mask("bob", None, BanMaskStyle::Host), "bob!*@*");
+    }
+}
@@ -0,0 +1,330 @@
+use std::fmt;
+use std::str::FromStr;
+
+use base64::prelude::*;
+use hmac::{Hmac, Mac};
+use irc::proto::Command;
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+
+/// Longest AUTHENTICATE payload per line, longer payloads are split.
+pub const CHUNK_SIZE: usize = 400;
+
+/// Most SCRAM iterations we agree to compute, so a hostile server cannot
+/// stall the client with an enormous count.
+pub const MAX_ITERATIONS: u32 = 100_000;
+
+/// Supported SASL mechanisms.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mechanism {
+    /// Account name and password in the clear, relying on TLS.
+    #[default]
+    Plain,
+    /// Authentication by the TLS client certificate.
+    External,
+    /// Salted challenge-response, the password never leaves the client.
+    ScramSha256,
+}
+
+impl Mechanism {
+    /// The name used in `AUTHENTICATE` and the `sasl` capability value.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mechanism::Plain => "PLAIN",
+            Mechanism::External => "EXTERNAL",
+            Mechanism::ScramSha256 => "SCRAM-SHA-256",
+        }
+    }
+}
+
+impl FromStr for Mechanism {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "plain" => Ok(Mechanism::Plain),
+            "external" => Ok(Mechanism::External),
+            "scram-sha-256" => Ok(Mechanism::ScramSha256),
+            _ => Err(format!("unsupported SASL mechanism: {}", s)),
+        }
+    }
+}
+
+/// Why an exchange was abandoned on our side.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SaslError {
+    /// The challenge was not valid base64 or lacked required attributes.
+    Malformed,
+    /// The server nonce does not extend the one we sent.
+    NonceMismatch,
+    /// The server could not prove it knows the password.
+    BadServerSignature,
+    /// The server asked for more than [`MAX_ITERATIONS`] SCRAM iterations.
+    TooManyIterations(u32),
+    /// The server reported an error inside the exchange.
+    Server(String),
+    /// A challenge arrived after the exchange was complete.
+    Unexpected,
+}
+
+impl fmt::Display for SaslError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaslError::Malformed => write!(f, "malformed challenge from server"),
+            SaslError::NonceMismatch => write!(f, "server nonce does not match ours"),
+            SaslError::BadServerSignature => write!(f, "server signature did not verify"),
+            SaslError::TooManyIterations(iterations) => write!(f, "server asked for {} iterations, more than {}", iterations, MAX_ITERATIONS),
+            SaslError::Server(err) => write!(f, "server error: {}", err),
+            SaslError::Unexpected => write!(f, "unexpected challenge"),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Step {
+    /// Waiting for the first challenge, usually an empty `+`.
+    Initial,
+    /// SCRAM client-first sent, waiting for server-first.
+    ScramFirst { nonce: String, client_first_bare: String },
+    /// SCRAM client-final sent, waiting for server-final.
+    ScramFinal { server_signature: Vec<u8> },
+    /// Everything is sent, waiting for 903 or 904.
+    Done,
+}
+
+/// One SASL exchange, fed the server's `AUTHENTICATE` lines.
+#[derive(Debug)]
+pub struct Sasl {
+    pub mechanism: Mechanism,
+    username: String,
+    password: String,
+    step: Step,
+    /// Challenge chunks received so far.
+    pending: String,
+}
+
+impl Sasl {
+    /// Constructs a new instance of [`Sasl`].
+    pub fn new(mechanism: Mechanism, username: &str, password: &str) -> Self {
+        Self {
+            mechanism,
+            username: username.to_string(),
+            password: password.to_string(),
+            step: Step::Initial,
+            pending: String::new(),
+        }
+    }
+
+    /// Starts, or restarts, the exchange.
+    pub fn start(&mut self) -> Command {
+        self.step = Step::Initial;
+        self.pending.clear();
+        Command::AUTHENTICATE(self.mechanism.name().to_string())
+    }
+
+    /// Handles one `AUTHENTICATE` line from the server and returns the lines
+    /// to answer with, none while further chunks of the challenge are due.
+    pub fn challenge(&mut self, data: &str) -> Result<Vec<Command>, SaslError> {
+        if data != "+" {
+            self.pending.push_str(data);
+        }
+        if data.len() == CHUNK_SIZE {
+            return Ok(vec![]);
+        }
+        let encoded = std::mem::take(&mut self.pending);
+        let challenge = BASE64_STANDARD.decode(encoded).map_err(|_| SaslError::Malformed)?;
+        let nonce = rand::thread_rng().sample_iter(&Alphanumeric).take(24).map(char::from).collect();
+        let response = self.respond(&challenge, nonce)?;
+        Ok(chunk(&response))
+    }
+
+    fn respond(&mut self, challenge: &[u8], nonce: String) -> Result<Vec<u8>, SaslError> {
+        match std::mem::replace(&mut self.step, Step::Done) {
+            Step::Initial => match self.mechanism {
+                Mechanism::Plain => Ok(format!("{}\x00{}\x00{}", self.username, self.username, self.password).into_bytes()),
+                Mechanism::External => Ok(vec![]),
+                Mechanism::ScramSha256 => {
+                    let client_first_bare = format!("n={},r={}", scram_name(&self.username), nonce);
+                    let response = format!("n,,{}", client_first_bare).into_bytes();
+                    self.step = Step::ScramFirst { nonce, client_first_bare };
+                    Ok(response)
+                },
+            },
+            Step::ScramFirst { nonce, client_first_bare } => {
+                let server_first = std::str::from_utf8(challenge).map_err(|_| SaslError::Malformed)?;
+                let attributes = scram_attributes(server_first)?;
+                let server_nonce = attributes.get('r').ok_or(SaslError::Malformed)?;
+                if !server_nonce.starts_with(&nonce) {
+                    return Err(SaslError::NonceMismatch);
+                }
+                let salt = attributes.get('s').and_then(|salt| BASE64_STANDARD.decode(salt).ok()).ok_or(SaslError::Malformed)?;
+                let iterations = attributes.get('i').and_then(|i| i.parse().ok()).ok_or(SaslError::Malformed)?;
+                if iterations > MAX_ITERATIONS {
+                    return Err(SaslError::TooManyIterations(iterations));
+                }
+
+                let mut salted = [0u8; 32];
+                pbkdf2::pbkdf2_hmac::<Sha256>(self.password.as_bytes(), &salt, iterations, &mut salted);
+                let client_key = hmac(&salted, b"Client Key");
+                let stored_key = Sha256::digest(&client_key);
+                // `biws` is the base64 of the GS2 header `n,,`.
+                let client_final = format!("c=biws,r={}", server_nonce);
+                let auth_message = format!("{},{},{}", client_first_bare, server_first, client_final);
+                let client_signature = hmac(&stored_key, auth_message.as_bytes());
+                let proof: Vec<u8> = client_key.iter().zip(client_signature).map(|(key, signature)| key ^ signature).collect();
+                let server_signature = hmac(&hmac(&salted, b"Server Key"), auth_message.as_bytes());
+
+                self.step = Step::ScramFinal { server_signature };
+                Ok(format!("{},p={}", client_final, BASE64_STANDARD.encode(proof)).into_bytes())
+            },
+            Step::ScramFinal { server_signature } => {
+                let server_final = std::str::from_utf8(challenge).map_err(|_| SaslError::Malformed)?;
+                let attributes = scram_attributes(server_final)?;
+                if let Some(err) = attributes.get('e') {
+                    return Err(SaslError::Server(err.to_string()));
+                }
+                let verifier = attributes.get('v').and_then(|v| BASE64_STANDARD.decode(v).ok()).ok_or(SaslError::Malformed)?;
+                if verifier != server_signature {
+                    return Err(SaslError::BadServerSignature);
+                }
+                Ok(vec![])
+            },
+            Step::Done => Err(SaslError::Unexpected),
+        }
+    }
+}
+
+/// Splits a response into `AUTHENTICATE` lines of at most [`CHUNK_SIZE`]
+/// bytes. An empty response, or one ending exactly on a chunk boundary,
+/// is terminated by `+`.
+pub fn chunk(response: &[u8]) -> Vec<Command> {
+    let encoded = BASE64_STANDARD.encode(response);
+    // Base64 is ASCII, so byte chunks are valid strings.
+    let mut lines: Vec<Command> = encoded.as_bytes()
+        .chunks(CHUNK_SIZE)
+        .map(|chunk| Command::AUTHENTICATE(String::from_utf8_lossy(chunk).into_owned()))
+        .collect();
+    if encoded.len().is_multiple_of(CHUNK_SIZE) {
+        lines.push(Command::AUTHENTICATE("+".to_string()));
+    }
+    lines
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Escapes `=` and `,` in a SCRAM user name.
+fn scram_name(name: &str) -> String {
+    name.replace('=', "=3D").replace(',', "=2C")
+}
+
+/// Parses `a=value,b=value` SCRAM attributes.
+fn scram_attributes(message: &str) -> Result<Attributes<'_>, SaslError> {
+    message.split(',')
+        .map(|attribute| {
+            let mut chars = attribute.chars();
+            match (chars.next(), chars.next()) {
+                (Some(name), Some('=')) => Ok((name, chars.as_str())),
+                _ => Err(SaslError::Malformed),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(Attributes)
+}
+
+struct Attributes<'a>(Vec<(char, &'a str)>);
+
+impl<'a> Attributes<'a> {
+    fn get(&self, name: char) -> Option<&'a str> {
+        self.0.iter().find(|(attribute, _)| *attribute == name).map(|(_, value)| *value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(command: &Command) -> String {
+        match command {
+            Command::AUTHENTICATE(payload) => payload.clone(),
+            _ => String::new(),
+        }
+    }
+
+    fn decode(lines: &[Command]) -> String {
+        let encoded: String = lines.iter().map(payload).filter(|payload| payload != "+").collect();
+        String::from_utf8(BASE64_STANDARD.decode(encoded).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn plain() {
+        let mut sasl = Sasl::new(Mechanism::Plain, "alice", "secret");
+        assert_eq!(payload(&sasl.start()), "PLAIN");
+        let lines = sasl.challenge("+").unwrap();
+        assert_eq!(decode(&lines), "alice\x00alice\x00secret");
+        assert_eq!(sasl.challenge("+"), Err(SaslError::Unexpected));
+    }
+
+    #[test]
+    fn external_sends_empty_response() {
+        let mut sasl = Sasl::new(Mechanism::External, "alice", "");
+        assert_eq!(payload(&sasl.start()), "EXTERNAL");
+        let lines = sasl.challenge("+").unwrap();
+        assert_eq!(lines.iter().map(payload).collect::<Vec<_>>(), vec!["+"]);
+    }
+
+    #[test]
+    fn chunks_long_payloads() {
+        let lines = chunk(&[b'a'; 300]);
+        assert_eq!(lines.iter().map(|line| payload(line).len()).collect::<Vec<_>>(), vec![400, 1]);
+        assert_eq!(payload(&lines[1]), "+");
+        let lines = chunk(&[b'a'; 301]);
+        assert_eq!(lines.iter().map(|line| payload(line).len()).collect::<Vec<_>>(), vec![400, 4]);
+
+        let mut sasl = Sasl::new(Mechanism::Plain, "alice", "secret");
+        sasl.start();
+        assert_eq!(sasl.challenge(&"A".repeat(CHUNK_SIZE)), Ok(vec![]));
+    }
+
+    #[test]
+    fn scram_sha_256_rfc7677() {
+        let mut sasl = Sasl::new(Mechanism::ScramSha256, "user", "pencil");
+        sasl.start();
+        let first = sasl.respond(b"", "rOprNGfwEbeRWgbNEkqO".to_string()).unwrap();
+        assert_eq!(first, b"n,,n=user,r=rOprNGfwEbeRWgbNEkqO");
+        let server_first = b"r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096";
+        let last = sasl.respond(server_first, String::new()).unwrap();
+        assert_eq!(
+            String::from_utf8(last).unwrap(),
+            "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="
+        );
+        let server_final = BASE64_STANDARD.encode("v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=");
+        let lines = sasl.challenge(&server_final).unwrap();
+        assert_eq!(lines.iter().map(payload).collect::<Vec<_>>(), vec!["+"]);
+    }
+
+    #[test]
+    fn scram_rejects_bad_server() {
+        let mut sasl = Sasl::new(Mechanism::ScramSha256, "user", "pencil");
+        sasl.start();
+        sasl.respond(b"", "abc".to_string()).unwrap();
+        assert_eq!(sasl.respond(b"r=xyz,s=AAAA,i=1", String::new()), Err(SaslError::NonceMismatch));
+
+        sasl.start();
+        sasl.respond(b"", "abc".to_string()).unwrap();
+        assert_eq!(sasl.respond(b"r=abcdef,s=AAAA,i=4000000000", String::new()), Err(SaslError::TooManyIterations(4_000_000_000)));
+
+        sasl.start();
+        sasl.respond(b"", "abc".to_string()).unwrap();
+        sasl.respond(b"r=abcdef,s=AAAA,i=1", String::new()).unwrap();
+        assert_eq!(sasl.respond(b"v=AAAA", String::new()), Err(SaslError::BadServerSignature));
+
+        sasl.start();
+        sasl.respond(b"", "abc".to_string()).unwrap();
+        sasl.respond(b"r=abcdef,s=AAAA,i=1", String::new()).unwrap();
+        assert_eq!(sasl.respond(b"e=invalid-proof", String::new()), Err(SaslError::Server("invalid-proof".to_string())));
+    }
+}
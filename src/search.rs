@@ -0,0 +1,230 @@
+use std::ops::Range;
+
+use chrono::NaiveDate;
+use regex::Regex;
+
+use crate::format::strip;
+use crate::isupport::ServerInfo;
+use crate::message::ChatLine;
+
+/// Most results a search lists.
+pub const MAX_RESULTS: usize = 500;
+
+/// Where a search looks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Scope {
+    /// The active buffer.
+    #[default]
+    Buffer,
+    /// Every open buffer.
+    Open,
+    /// The logs of every buffer of the network on disk.
+    Logs,
+}
+
+/// What a line's text must contain.
+#[derive(Clone, Debug)]
+pub enum Pattern {
+    /// A case-insensitive substring, kept lowercased.
+    Text(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    /// Byte ranges of every match in a text.
+    pub fn find(&self, text: &str) -> Vec<Range<usize>> {
+        match self {
+            Pattern::Text(needle) if needle.is_empty() => vec![],
+            Pattern::Text(needle) => {
+                // Lowercasing can change byte lengths, so only trust it when it does not.
+                let haystack = text.to_lowercase();
+                if haystack.len() != text.len() {
+                    let whole = 0..text.len();
+                    return haystack.contains(needle.as_str()).then_some(whole).into_iter().collect();
+                }
+                haystack.match_indices(needle.as_str()).map(|(start, found)| start..start + found.len()).collect()
+            },
+            Pattern::Regex(regex) => regex.find_iter(text).filter(|found| !found.is_empty()).map(|found| found.range()).collect(),
+        }
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        match self {
+            Pattern::Text(needle) => text.to_lowercase().contains(needle.as_str()),
+            Pattern::Regex(regex) => regex.is_match(text),
+        }
+    }
+}
+
+/// Incremental search within the active buffer.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BufferSearch {
+    pub query: String,
+    /// Whether keys edit the query, rather than move between matches.
+    pub editing: bool,
+    /// Line index and byte range of the selected match.
+    pub current: Option<(usize, Range<usize>)>,
+}
+
+impl BufferSearch {
+    pub fn pattern(&self) -> Pattern {
+        Pattern::Text(self.query.to_lowercase())
+    }
+
+    /// Every match in the rendered text of the lines, in order.
+    pub fn matches<'a>(&self, texts: impl Iterator<Item = &'a str>) -> Vec<(usize, Range<usize>)> {
+        let pattern = self.pattern();
+        texts.enumerate()
+            .flat_map(|(index, text)| pattern.find(text).into_iter().map(move |range| (index, range)))
+            .collect()
+    }
+
+    /// Selects the most recent match.
+    pub fn select_last(&mut self, matches: &[(usize, Range<usize>)]) {
+        self.current = matches.last().cloned();
+    }
+
+    /// Selects the match before the current one, or with `newer` the one
+    /// after it, staying on the first or last match.
+    pub fn step(&mut self, matches: &[(usize, Range<usize>)], newer: bool) {
+        let position = self.current.as_ref().and_then(|current| matches.iter().position(|found| found == current));
+        let next = match (position, newer) {
+            (None, _) => matches.len().checked_sub(1),
+            (Some(position), false) => Some(position.saturating_sub(1)),
+            (Some(position), true) => Some((position + 1).min(matches.len() - 1)),
+        };
+        self.current = next.and_then(|next| matches.get(next)).cloned();
+    }
+}
+
+/// A parsed `/search` line.
+#[derive(Clone, Debug)]
+pub struct Query {
+    pub scope: Scope,
+    pub pattern: Pattern,
+    /// Only lines sent by this nick.
+    pub nick: Option<String>,
+    /// First day to include.
+    pub from: Option<NaiveDate>,
+    /// Last day to include.
+    pub to: Option<NaiveDate>,
+}
+
+impl Query {
+    /// Parses `[-all|-logs] [-regex] [-nick <nick>] [-from <date>] [-to <date>] <pattern>`,
+    /// dates written as YYYY-MM-DD. `--` ends the options.
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut scope = Scope::Buffer;
+        let mut regex = false;
+        let mut nick = None;
+        let mut from = None;
+        let mut to = None;
+        let mut rest = input.trim_start();
+        loop {
+            let (word, after) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            let after = after.trim_start();
+            let mut value = || -> Result<&str, String> {
+                let (value, after) = after.split_once(char::is_whitespace).unwrap_or((after, ""));
+                if value.is_empty() {
+                    return Err(format!("{} needs a value", word));
+                }
+                rest = after.trim_start();
+                Ok(value)
+            };
+            let date = |value: &str| NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| format!("Not a date (YYYY-MM-DD): {}", value));
+            match word {
+                "-all" => scope = Scope::Open,
+                "-logs" => scope = Scope::Logs,
+                "-regex" | "-re" => regex = true,
+                "-nick" => {
+                    nick = Some(value()?.to_string());
+                    continue;
+                },
+                "-from" => {
+                    from = Some(date(value()?)?);
+                    continue;
+                },
+                "-to" => {
+                    to = Some(date(value()?)?);
+                    continue;
+                },
+                "--" => {
+                    rest = after;
+                    break;
+                },
+                _ => break,
+            }
+            rest = after;
+        }
+        if rest.is_empty() && nick.is_none() {
+            return Err("Nothing to search for".to_string());
+        }
+        let pattern = match regex {
+            true => Pattern::Regex(Regex::new(&format!("(?i){}", rest)).map_err(|err| err.to_string())?),
+            false => Pattern::Text(rest.to_lowercase()),
+        };
+        Ok(Self { scope, pattern, nick, from, to })
+    }
+
+    /// Whether a line passes the filters and contains the pattern.
+    pub fn matches(&self, line: &ChatLine, server_info: &ServerInfo) -> bool {
+        let day = line.time.date_naive();
+        self.nick.as_ref().is_none_or(|nick| line.sender.as_ref().is_some_and(|sender| server_info.eq(sender, nick)))
+            && self.from.is_none_or(|from| day >= from)
+            && self.to.is_none_or(|to| day <= to)
+            && self.pattern.is_match(&strip(&line.text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::LineKind;
+    use chrono::{Local, TimeZone};
+
+    #[test]
+    fn parse() {
+        let query = Query::parse("-all -nick Alice -from 2024-03-01 hello world").unwrap();
+        assert_eq!(query.scope, Scope::Open);
+        assert_eq!(query.nick.as_deref(), Some("Alice"));
+        assert_eq!(query.from, NaiveDate::from_ymd_opt(2024, 3, 1));
+        assert!(matches!(query.pattern, Pattern::Text(ref text) if text == "hello world"));
+
+        assert!(matches!(Query::parse("-- -all").unwrap().pattern, Pattern::Text(ref text) if text == "-all"));
+        assert!(Query::parse("-from yesterday x").is_err());
+        assert!(Query::parse("-regex (").is_err());
+        assert!(Query::parse("-logs").is_err());
+    }
+
+    #[test]
+    fn matching() {
+        let info = ServerInfo::default();
+        let mut line = ChatLine::new(LineKind::Privmsg, Some("alice"), Some("#rust"), "Borrow checker says no");
+        line.time = Local.with_ymd_and_hms(2024, 3, 2, 9, 0, 0).unwrap();
+        assert!(Query::parse("CHECKER").unwrap().matches(&line, &info));
+        assert!(Query::parse("-nick ALICE -to 2024-03-02 says").unwrap().matches(&line, &info));
+        assert!(!Query::parse("-nick bob says").unwrap().matches(&line, &info));
+        assert!(!Query::parse("-from 2024-03-03 says").unwrap().matches(&line, &info));
+        assert!(Query::parse("-regex ^borrow\\s+\\w+").unwrap().matches(&line, &info));
+
+        let pattern = Query::parse("o").unwrap().pattern;
+        assert_eq!(pattern.find("foo"), vec![1..2, 2..3]);
+    }
+
+    #[test]
+    fn incremental() {
+        let mut search = BufferSearch { query: "Ab".to_string(), ..Default::default() };
+        let matches = search.matches(["abab", "x", "cab"].into_iter());
+        assert_eq!(matches, vec![(0, 0..2), (0, 2..4), (2, 1..3)]);
+        search.select_last(&matches);
+        assert_eq!(search.current, Some((2, 1..3)));
+        search.step(&matches, false);
+        search.step(&matches, false);
+        search.step(&matches, false);
+        assert_eq!(search.current, Some((0, 0..2)));
+        search.step(&matches, true);
+        assert_eq!(search.current, Some((0, 2..4)));
+        search.step(&[], true);
+        assert_eq!(search.current, None);
+    }
+}
@@ -1,62 +1,278 @@
 use ratatui::{
     layout::{Alignment, Constraint, Layout, Margin, Position},
     style::{Color, Style, Stylize},
+    text::{Line, Span, Text},
     widgets::{Block, BorderType, Borders, Paragraph, Scrollbar, ScrollbarOrientation, Tabs, Wrap},
     Frame,
 };
 
-use std::{collections::HashMap as Hashmap, u16};
+use std::ops::Range;
+use std::time::Instant;
 
-use crate::app::App;
+use crate::app::{App, Mode};
+use crate::buffer::{Buffer, BufferKind};
+use crate::colors::NickPainter;
+use crate::connection::ConnectionState;
+use crate::format;
+use crate::message::{ChatLine, Delivery, LineKind};
+use crate::search::BufferSearch;
+
+/// Timestamp column format used unless the `timestamp_format` option is set.
+pub const DEFAULT_TIMESTAMP_FORMAT: &str = "%H:%M";
+
+/// Formats a scrollback line into readable text.
+pub fn format_line(line: &ChatLine) -> String {
+    let sender = line.sender_name();
+    let target = line.target.as_deref().unwrap_or("");
+    match line.kind {
+        LineKind::Privmsg => format!("{}: {}", sender, line.text),
+        LineKind::Action => format!("* {} {}", sender, line.text),
+        LineKind::Notice => format!("-{}- {}", sender, line.text),
+        LineKind::Join => format!("--> {} joined {}", sender, target),
+        LineKind::Part if line.text.is_empty() => format!("<-- {} left {}", sender, target),
+        LineKind::Part => format!("<-- {} left {} ({})", sender, target, line.text),
+        LineKind::Quit if line.text.is_empty() => format!("<-- {} quit", sender),
+        LineKind::Quit => format!("<-- {} quit ({})", sender, line.text),
+        LineKind::Nick => format!("-- {} is now known as {}", sender, line.text),
+        LineKind::Kick => match line.text.split_once(' ') {
+            Some((nick, reason)) => format!("<-- {} kicked {} from {} ({})", sender, nick, target, reason),
+            None => format!("<-- {} kicked {} from {}", sender, line.text, target),
+        },
+        LineKind::Mode => format!("-- {} sets mode {} on {}", sender, line.text, target),
+        LineKind::Topic => format!("-- {} changed the topic of {} to: {}", sender, target, line.text),
+        LineKind::Error => format!("!! {}", line.text),
+        LineKind::Numeric(_) | LineKind::Status | LineKind::Other => format!("-- {}", line.text),
+        LineKind::Separator => format!("--- {} ---", line.text),
+    }
+}
+
+/// Styles a text, following its mIRC formatting unless `strip` is set.
+fn styled(text: &str, style: Style, strip: bool) -> Vec<Span<'static>> {
+    match strip {
+        true => vec![Span::styled(format::strip(text), style)],
+        false => format::parse(text, style),
+    }
+}
+
+/// Where the sender starts in [`format_line`] output, for lines naming one.
+fn sender_offset(kind: LineKind) -> Option<usize> {
+    match kind {
+        LineKind::Privmsg => Some(0),
+        LineKind::Notice => Some(1),
+        LineKind::Action => Some(2),
+        LineKind::Nick | LineKind::Mode | LineKind::Topic => Some(3),
+        LineKind::Join | LineKind::Part | LineKind::Quit | LineKind::Kick => Some(4),
+        _ => None,
+    }
+}
+
+/// Restyles byte ranges of styled spans. The ranges are sorted and do not overlap.
+fn overlay(spans: Vec<Span<'static>>, ranges: &[(Range<usize>, Style)]) -> Vec<Span<'static>> {
+    if ranges.is_empty() {
+        return spans;
+    }
+    let mut restyled = vec![];
+    let mut offset = 0;
+    for span in spans {
+        let content = span.content.as_ref();
+        let end = offset + content.len();
+        let mut cursor = offset;
+        for (range, style) in ranges.iter().filter(|(range, _)| range.start < end && range.end > offset) {
+            let (start, stop) = (range.start.max(cursor), range.end.min(end));
+            if cursor < start {
+                restyled.push(Span::styled(content[cursor - offset..start - offset].to_string(), span.style));
+            }
+            restyled.push(Span::styled(content[start - offset..stop - offset].to_string(), span.style.patch(*style)));
+            cursor = stop;
+        }
+        if cursor < end {
+            restyled.push(Span::styled(content[cursor - offset..].to_string(), span.style));
+        }
+        offset = end;
+    }
+    restyled
+}
+
+/// Colors the sender of a rendered line `len` bytes long, and the nicks
+/// its message mentions.
+fn nick_ranges(line: &ChatLine, len: usize, nicks: &NickPainter) -> Vec<(Range<usize>, Style)> {
+    let mut ranges = vec![];
+    if let (Some(offset), Some(sender)) = (sender_offset(line.kind), line.sender.as_deref()) {
+        ranges.push((offset..offset + sender.len(), Style::default().fg(nicks.color(sender))));
+    }
+    match line.kind {
+        LineKind::Privmsg | LineKind::Action | LineKind::Notice => {
+            let message = format::strip(&line.text);
+            let start = len - message.len();
+            ranges.extend(nicks.mentions(&message).into_iter()
+                .map(|(range, color)| (start + range.start..start + range.end, Style::default().fg(color))));
+        },
+        LineKind::Nick => ranges.push((len - line.text.len()..len, Style::default().fg(nicks.color(&line.text)))),
+        _ => {},
+    }
+    ranges
+}
+
+/// Styles a scrollback line, coloring nicks on lines the server confirmed.
+fn line_spans(line: &ChatLine, style: Style, strip: bool, nicks: Option<&NickPainter>) -> Vec<Span<'static>> {
+    let spans = styled(&format_line(line), style, strip);
+    match nicks.filter(|_| line.delivery == Delivery::Confirmed) {
+        Some(nicks) => {
+            let len = spans.iter().map(|span| span.content.len()).sum();
+            overlay(spans, &nick_ranges(line, len, nicks))
+        },
+        None => spans,
+    }
+}
+
+/// Highlights the matches of a search in styled spans, and the selected one.
+fn highlight(index: usize, spans: Vec<Span<'static>>, search: Option<&BufferSearch>) -> Vec<Span<'static>> {
+    let Some(search) = search.filter(|search| !search.query.is_empty()) else {
+        return spans;
+    };
+    let text: String = spans.iter().map(|span| span.content.as_ref()).collect();
+    let ranges: Vec<(Range<usize>, Style)> = search.pattern().find(&text).into_iter().map(|range| {
+        let selected = search.current.as_ref().is_some_and(|(line, current)| *line == index && *current == range);
+        let found = match selected {
+            true => Style::default().fg(Color::Black).bg(Color::LightRed),
+            false => Style::default().fg(Color::Black).bg(Color::Yellow),
+        };
+        (range, found)
+    }).collect();
+    overlay(spans, &ranges)
+}
+
+/// Builds the rendered scrollback of a buffer, prefixing each line with its
+/// timestamp and marking where the day changes. Matches of an incremental
+/// search are highlighted, mIRC formatting is followed unless `strip` is set
+/// and nicks are colored when `nicks` is given.
+pub fn buffer_lines(lines: &[ChatLine], time_format: &str, search: Option<&BufferSearch>, strip: bool, nicks: Option<&NickPainter>) -> Vec<Line<'static>> {
+    let dim = Style::default().fg(Color::DarkGray);
+    let mut rendered = vec![];
+    let mut previous_day = None;
+    for (index, line) in lines.iter().enumerate() {
+        if line.kind == LineKind::Separator {
+            rendered.push(Line::styled(format_line(line), dim));
+            continue;
+        }
+        let day = line.time.date_naive();
+        if previous_day.is_some_and(|previous| previous != day) {
+            rendered.push(Line::styled(format!("--- {} ---", day.format("%A, %-d %B %Y")), dim));
+        }
+        previous_day = Some(day);
+        let mut spans = vec![];
+        if !time_format.is_empty() {
+            spans.push(Span::styled(format!("{} ", line.time.format(time_format)), dim));
+        }
+        let failed = Style::default().fg(Color::Red);
+        let style = match line.delivery {
+            Delivery::Confirmed => Style::default(),
+            Delivery::Pending => dim,
+            Delivery::Failed => failed,
+        };
+        spans.extend(highlight(index, line_spans(line, style, strip, nicks), search));
+        if line.delivery == Delivery::Failed {
+            spans.push(Span::styled(" (not delivered)", failed));
+        }
+        rendered.push(Line::from(spans));
+    }
+    rendered
+}
+
+/// Builds the search results, each with the buffer it was found in, the
+/// selected one highlighted.
+pub fn search_lines(lines: &[ChatLine], selected: usize, time_format: &str, strip: bool, nicks: Option<&NickPainter>) -> Vec<Line<'static>> {
+    lines.iter().enumerate().map(|(index, line)| {
+        let mut spans = vec![];
+        if !time_format.is_empty() {
+            spans.push(Span::styled(format!("{} ", line.time.format(&format!("%Y-%m-%d {}", time_format))), Style::default().fg(Color::DarkGray)));
+        }
+        spans.push(Span::styled(format!("{} ", line.target.as_deref().unwrap_or("")), Style::default().fg(Color::Cyan)));
+        spans.extend(line_spans(line, Style::default(), strip, nicks));
+        let rendered = Line::from(spans);
+        match index == selected {
+            true => rendered.reversed(),
+            false => rendered,
+        }
+    }).collect()
+}
+
+/// Rendered scrollback of a buffer, up to line `end`.
+fn rendered_lines(buffer: &Buffer, end: usize, time_format: &str, search: Option<&BufferSearch>, nicks: &NickPainter) -> Vec<Line<'static>> {
+    let lines = &buffer.lines[..end.min(buffer.lines.len())];
+    match buffer.kind {
+        BufferKind::Search => search_lines(lines, buffer.selected, time_format, buffer.strip_formatting, Some(nicks)),
+        _ => buffer_lines(lines, time_format, search, buffer.strip_formatting, Some(nicks)),
+    }
+}
+
+/// Input box of [`Mode::Search`], with the position of the selected match.
+fn search_box(app: &App) -> Paragraph<'static> {
+    let matches = app.buffer_search_matches();
+    let position = app.buffer_search.current.as_ref().and_then(|current| matches.iter().position(|found| found == current));
+    let count = match (position, matches.len()) {
+        (_, 0) if !app.buffer_search.query.is_empty() => " (no matches)".to_string(),
+        (Some(position), total) => format!(" ({}/{})", position + 1, total),
+        _ => String::new(),
+    };
+    let keys = match app.buffer_search.editing {
+        true => "Search - Enter to browse, Esc to leave",
+        false => "Search - n/Up older, N/Down newer, / new search, Esc to leave",
+    };
+    Paragraph::new(Line::from(vec![Span::raw(app.buffer_search.query.clone()), Span::raw(count).dim()]))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .title(keys)
+                .title_style(Style::default().fg(Color::Yellow))
+                .style(Style::default().fg(Color::White)),
+        ).alignment(Alignment::Left)
+}
+
+/// Status bar below the input: nick, network, connection state and lag.
+pub fn status_bar(app: &App) -> Line<'static> {
+    let network = app.network_name();
+    let state = match app.connection {
+        ConnectionState::Connected => Span::styled(app.connection.label(), Style::default().fg(Color::Green)),
+        ConnectionState::Disconnected { .. } => Span::styled(app.connection.label(), Style::default().fg(Color::Red)),
+        _ => Span::styled(app.connection.label(), Style::default().fg(Color::Yellow)),
+    };
+    let mut spans = vec![
+        Span::raw(format!(" {} ", app.username)),
+        Span::raw("| ").dim(),
+        Span::raw(format!("{} ", network)),
+        Span::raw("| ").dim(),
+        state,
+    ];
+    if app.connection == ConnectionState::Connected {
+        if let Some(lag) = app.lag.current(Instant::now()) {
+            let style = match lag.as_secs() {
+                0..=1 => Style::default(),
+                2..=9 => Style::default().fg(Color::Yellow),
+                _ => Style::default().fg(Color::Red),
+            };
+            spans.push(Span::raw(" | ").dim());
+            spans.push(Span::styled(format!("lag {:.2}s", lag.as_secs_f64()), style));
+        }
+    }
+    Line::from(spans)
+}
 
 /// Renders the user interface widgets.
 pub fn render(app: &mut App, frame: &mut Frame) {
-    let messages = app.messages.clone();
-    let mut tabs:Hashmap<String, Paragraph> = Hashmap::new();
-    let chunks = Layout::vertical([Constraint::Percentage(90), Constraint::Percentage(10)]).split(frame.size());
+    let chunks = Layout::vertical([Constraint::Percentage(90), Constraint::Percentage(10), Constraint::Length(1)]).split(frame.size());
     let vert_chunks = Layout::horizontal([Constraint::Percentage(90), Constraint::Percentage(10)]).split(chunks[0]);
     let tab_chunks = Layout::vertical([Constraint::Length(3), Constraint::Min(1)]).split(vert_chunks[0]);
 
-    for title in &app.tab_titles {
-        let idx = app.tab_titles.iter().position(|x| x == title).unwrap();
-        match messages.get(title) {
-            None => {}
-            Some(_) => {
-                if app.vertical_scroll.get(app.selected_tab).is_none() {
-                    app.vertical_scroll.push(0);
-                    app.vertical_scroll_state.push(Default::default());
-                };
-                if app.horizontal_scroll.get(app.selected_tab).is_none() {
-                    app.horizontal_scroll.push(0);
-                    app.horizontal_scroll_state.push(Default::default());
-                };
-                let para = Paragraph::new(messages.get(title).unwrap().join(""))
-                                            .block(
-                                                    Block::default()
-                                                        .borders(Borders::ALL)
-                                                        .border_type(BorderType::Rounded)
-                                                        .title(title.to_string())
-                                                        .title_style(Style::default().fg(Color::Yellow))
-                                                        .style(Style::default().fg(Color::White)),
-                                                    )
-                                            .alignment(Alignment::Left)
-                                            .scroll((app.vertical_scroll[app.selected_tab], 0))
-                                            .wrap(Wrap { trim: true });
-                let needed = para.line_count(tab_chunks[1].width);
-                let available = tab_chunks[1].bottom() - tab_chunks[1].y;
-                // if idx == app.selected_tab {
-                //     println!("{}", tab_chunks[1].width);
-                // }
-                if idx == app.selected_tab && app.vertical_scroll[app.selected_tab] < needed.saturating_sub(available as usize) as u16 {
-                    app.vertical_scroll[app.selected_tab] = app.vertical_scroll[app.selected_tab].saturating_add(1);
-                }
-                tabs.insert(title.clone(), para);
-                app.vertical_scroll_state[app.selected_tab] = app.vertical_scroll_state[app.selected_tab].content_length(needed);
-                app.horizontal_scroll_state[app.selected_tab] = app.horizontal_scroll_state[app.selected_tab].content_length(needed);
-                            }
+    let titles: Vec<Line> = app.buffers.iter().map(|buffer| {
+        match (buffer.unread, buffer.highlights) {
+            (0, _) => Line::from(buffer.name().to_string()),
+            (unread, 0) => Line::from(format!("{} ({})", buffer.name(), unread)).style(Style::default().fg(Color::Cyan)),
+            (unread, _) => Line::from(format!("{} ({})", buffer.name(), unread)).style(Style::default().fg(Color::Red).bold()),
         }
-    }
-    let tabs_list = Tabs::new(app.tab_titles.clone())
+    }).collect();
+    let tabs_list = Tabs::new(titles)
         .block(
             Block::default()
                 .borders(Borders::ALL)
@@ -66,19 +282,56 @@ pub fn render(app: &mut App, frame: &mut Frame) {
                 .style(Style::default().fg(Color::White)),
         ).highlight_style(Style::default().fg(Color::Yellow))
         .select(app.selected_tab);
-    // let main = Paragraph::new("")
-        // .block(
-        //     Block::default()
-        //         .borders(Borders::ALL)
-        //         .border_type(BorderType::Rounded)
-        //         .title("Messages")
-        //         .title_style(Style::default().fg(Color::Yellow))
-        //         .style(Style::default().fg(Color::White)),
-        // )
-        // .alignment(Alignment::Left)
-        // .scroll((app.vertical_scroll, app.horizontal_scroll))
-        // .wrap(Wrap { trim: false });
-    let users = Paragraph::new(app.active_channel_users.join("\n"))
+
+    let nick_colors = app.nick_colors.clone();
+    let casemapping = app.server_info.casemapping;
+    let nick_list: Vec<Line> = app.active().nick_list(&app.server_info).into_iter().map(|entry| {
+        let nick = entry.trim_start_matches(|c| app.server_info.prefixes.iter().any(|(_, prefix)| *prefix == c));
+        let prefix = entry[..entry.len() - nick.len()].to_string();
+        Line::from(vec![Span::raw(prefix), Span::styled(nick.to_string(), Style::default().fg(nick_colors.color(nick, casemapping)))])
+    }).collect();
+    // Nicks colored when mentioned: the channel members, the other side of a query and us.
+    let active = app.active();
+    let query = (active.kind == BufferKind::Query).then(|| active.name());
+    let known = active.members.keys().map(|nick| nick.name()).chain(query).chain([app.username.as_str()]);
+    let nicks = NickPainter::new(&nick_colors, casemapping, known);
+    let time_format = app.timestamp_format();
+    let search = matches!(app.mode, Mode::Search).then(|| app.buffer_search.clone());
+    let buffer = app.active_mut();
+    let title = match &buffer.topic {
+        Some(topic) => format!("{} - {}", buffer.name(), topic),
+        None => buffer.name().to_string(),
+    };
+    let para = Paragraph::new(Text::from(rendered_lines(buffer, buffer.lines.len(), &time_format, search.as_ref(), &nicks)))
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .title(title)
+                .title_style(Style::default().fg(Color::Yellow))
+                .style(Style::default().fg(Color::White)),
+        )
+        .alignment(Alignment::Left)
+        .wrap(Wrap { trim: true });
+    let needed = para.line_count(tab_chunks[1].width);
+    let available = tab_chunks[1].bottom() - tab_chunks[1].y;
+    // Frames are only drawn when state changes, so catch up to the bottom in one go.
+    buffer.max_scroll = needed.saturating_sub(available as usize) as u16;
+    // Bring a line into view with some context above it.
+    if let Some(line) = buffer.jump_to.take() {
+        let above = Paragraph::new(Text::from(rendered_lines(buffer, line, &time_format, None, &nicks)))
+            .wrap(Wrap { trim: true })
+            .line_count(tab_chunks[1].width.saturating_sub(2));
+        buffer.scroll = (above.saturating_sub(available.saturating_sub(2) as usize / 3) as u16).min(buffer.max_scroll);
+        buffer.follow = false;
+    }
+    if buffer.follow || buffer.scroll > buffer.max_scroll {
+        buffer.scroll = buffer.max_scroll;
+    }
+    buffer.scroll_state = buffer.scroll_state.content_length(needed).position(buffer.scroll as usize);
+    let para = para.scroll((buffer.scroll, 0));
+
+    let users = Paragraph::new(Text::from(nick_list))
         .block(
             Block::default()
                 .borders(Borders::ALL)
@@ -87,7 +340,9 @@ pub fn render(app: &mut App, frame: &mut Frame) {
                 .title_style(Style::default().fg(Color::Yellow))
                 .style(Style::default().fg(Color::White)),
         ).alignment(Alignment::Left);
-    let input_box = Paragraph::new(app.input.as_str())
+    // Formatting codes show as markers rather than invisible characters.
+    let input = Line::from(format::markers(&app.input, Style::default()));
+    let input_box = Paragraph::new(input.clone())
         .block(
             Block::default()
                 .borders(Borders::ALL)
@@ -96,7 +351,7 @@ pub fn render(app: &mut App, frame: &mut Frame) {
                 .title_style(Style::default().fg(Color::Yellow))
                 .style(Style::default().fg(Color::White)),
         ).alignment(Alignment::Left);
-    let command_box = Paragraph::new(app.input.as_str())
+    let command_box = Paragraph::new(input)
         .block(
             Block::default()
             .borders(Borders::ALL)
@@ -105,25 +360,110 @@ pub fn render(app: &mut App, frame: &mut Frame) {
             .title_style(Style::default().fg(Color::Yellow))
             .style(Style::default().fg(Color::White)),
         ).alignment(Alignment::Left);
-            
+
     frame.render_widget(tabs_list, tab_chunks[0]);
-    if let Some(tab) = tabs.get(&app.tab_titles[app.selected_tab]) {
-        frame.render_widget(tab, tab_chunks[1]);
-        frame.render_stateful_widget(Scrollbar::new(ScrollbarOrientation::VerticalRight), tab_chunks[1].inner(&Margin {
-            vertical: 1,
-            horizontal: 1,
-        }), &mut app.vertical_scroll_state[app.selected_tab]);
-    }
+    frame.render_widget(para, tab_chunks[1]);
+    frame.render_stateful_widget(Scrollbar::new(ScrollbarOrientation::VerticalRight), tab_chunks[1].inner(&Margin {
+        vertical: 1,
+        horizontal: 1,
+    }), &mut app.active_mut().scroll_state);
     match app.mode {
-        crate::app::Mode::Normal => frame.render_widget(input_box, chunks[1]),
-        crate::app::Mode::Command => frame.render_widget(command_box, chunks[1]),
+        Mode::Normal => frame.render_widget(input_box, chunks[1]),
+        Mode::Command => frame.render_widget(command_box, chunks[1]),
+        Mode::Search => frame.render_widget(search_box(app), chunks[1]),
     }
+    frame.render_widget(Paragraph::new(status_bar(app)), chunks[2]);
     match app.show_users {
         true => frame.render_widget(users, vert_chunks[1]),
         false => {}
     }
-    // frame.render_stateful_widget(Scrollbar::new(ScrollbarOrientation::VerticalRight), tab_chunks[1].inner(&Margin {
-    //     vertical: 1,
-    //     horizontal: 1,
-    // }), &mut app.vertical_scroll_state[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Local, TimeZone};
+    use crate::colors::NickColors;
+    use crate::isupport::CaseMapping;
+    use ratatui::style::Modifier;
+
+    #[test]
+    fn line_formats() {
+        let line = |kind, target: Option<&str>, text: &str| format_line(&ChatLine::new(kind, Some("alice"), target, text));
+        assert_eq!(line(LineKind::Privmsg, Some("#rust"), "hi"), "alice: hi");
+        assert_eq!(line(LineKind::Action, Some("#rust"), "waves"), "* alice waves");
+        assert_eq!(line(LineKind::Notice, Some("me"), "psst"), "-alice- psst");
+        assert_eq!(line(LineKind::Join, Some("#rust"), ""), "--> alice joined #rust");
+        assert_eq!(line(LineKind::Part, Some("#rust"), "bye"), "<-- alice left #rust (bye)");
+        assert_eq!(line(LineKind::Quit, None, ""), "<-- alice quit");
+        assert_eq!(line(LineKind::Kick, Some("#rust"), "bob spam"), "<-- alice kicked bob from #rust (spam)");
+        assert_eq!(line(LineKind::Kick, Some("#rust"), "bob"), "<-- alice kicked bob from #rust");
+        assert_eq!(line(LineKind::Nick, None, "alicia"), "-- alice is now known as alicia");
+        assert_eq!(line(LineKind::Topic, Some("#rust"), "Rust"), "-- alice changed the topic of #rust to: Rust");
+        assert_eq!(line(LineKind::Error, None, "Closing link"), "!! Closing link");
+    }
+
+    #[test]
+    fn timestamps_and_day_separators() {
+        let mut first = ChatLine::new(LineKind::Privmsg, Some("alice"), Some("#rust"), "late");
+        first.time = Local.with_ymd_and_hms(2024, 3, 1, 23, 59, 0).unwrap();
+        let mut second = ChatLine::new(LineKind::Privmsg, Some("bob"), Some("#rust"), "early");
+        second.time = Local.with_ymd_and_hms(2024, 3, 2, 0, 1, 0).unwrap();
+        let lines = buffer_lines(&[first.clone(), second], "%H:%M", None, false, None);
+        let text: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        assert_eq!(text, vec!["23:59 alice: late", "--- Saturday, 2 March 2024 ---", "00:01 bob: early"]);
+        assert_eq!(buffer_lines(&[first], "", None, false, None)[0].to_string(), "alice: late");
+    }
+
+    #[test]
+    fn search_highlights() {
+        let line = ChatLine::new(LineKind::Privmsg, Some("alice"), Some("#rust"), "late and later");
+        let search = BufferSearch { query: "LATE".to_string(), editing: false, current: Some((0, 16..20)) };
+        let rendered = &buffer_lines(&[line], "", Some(&search), false, None)[0];
+        assert_eq!(rendered.to_string(), "alice: late and later");
+        let styled: Vec<(&str, Option<Color>)> = rendered.spans.iter().map(|span| (span.content.as_ref(), span.style.bg)).collect();
+        assert_eq!(styled, vec![
+            ("alice: ", None),
+            ("late", Some(Color::Yellow)),
+            (" and ", None),
+            ("late", Some(Color::LightRed)),
+            ("r", None),
+        ]);
+    }
+
+    #[test]
+    fn formatting_under_highlights() {
+        let line = ChatLine::new(LineKind::Privmsg, Some("alice"), Some("#rust"), "\x02bold\x02 text");
+        let search = BufferSearch { query: "d t".to_string(), editing: false, current: None };
+        let rendered = &buffer_lines(std::slice::from_ref(&line), "", Some(&search), false, None)[0];
+        let styled: Vec<(&str, bool, Option<Color>)> = rendered.spans.iter()
+            .map(|span| (span.content.as_ref(), span.style.add_modifier.contains(Modifier::BOLD), span.style.bg))
+            .collect();
+        assert_eq!(styled, vec![
+            ("alice: ", false, None),
+            ("bol", true, None),
+            ("d", true, Some(Color::Yellow)),
+            (" t", false, Some(Color::Yellow)),
+            ("ext", false, None),
+        ]);
+        let stripped = &buffer_lines(&[line], "", None, true, None)[0];
+        assert_eq!(stripped.spans.len(), 1);
+        assert_eq!(stripped.to_string(), "alice: bold text");
+    }
+
+    #[test]
+    fn nick_colors() {
+        let colors = NickColors { palette: vec![Color::Green], overrides: vec![("bob".to_string(), Color::Magenta)] };
+        let nicks = NickPainter::new(&colors, CaseMapping::Ascii, ["Bob"].into_iter());
+        let line = ChatLine::new(LineKind::Privmsg, Some("alice"), Some("#rust"), "hi bob");
+        let rendered = &buffer_lines(&[line], "", None, false, Some(&nicks))[0];
+        let styled: Vec<(&str, Option<Color>)> = rendered.spans.iter().map(|span| (span.content.as_ref(), span.style.fg)).collect();
+        assert_eq!(styled, vec![("alice", Some(Color::Green)), (": hi ", None), ("bob", Some(Color::Magenta))]);
+
+        let line = ChatLine::new(LineKind::Nick, Some("alice"), None, "bob");
+        let rendered = &buffer_lines(&[line], "", None, false, Some(&nicks))[0];
+        assert_eq!(rendered.spans[0].content, "-- ");
+        assert_eq!(rendered.spans[1].style.fg, Some(Color::Green));
+        assert_eq!(rendered.spans.last().unwrap().style.fg, Some(Color::Magenta));
+    }
 }